@@ -0,0 +1,46 @@
+//! Times the searches which have a faster path against the general way of doing the same
+//! search, run with `cargo bench`. Each line is the average time of one call.
+use rejects::rejects::Rejects;
+use std::hint::black_box;
+use std::time::Instant;
+
+fn bench<T, F: FnMut() -> T>(name: &str, iterations: u32, mut f: F) {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+    println!("{:<48} {:>12.2?}", name, start.elapsed() / iterations);
+}
+
+fn main() {
+    let words = "lorem ipsum dolor sit amet consectetur adipiscing elit ".repeat(200);
+
+    // a union of literals is searched for with Aho–Corasick, the '[' keeps the same pattern on
+    // the NFA
+    let keywords = Rejects::new(r"while|return|yield|match").unwrap();
+    let keywords_nfa = Rejects::new(r"[w]hile|return|yield|match").unwrap();
+    let text = format!("{}return", words);
+    bench("contains, literals", 100, || keywords.contains(&text));
+    bench("contains, NFA", 100, || keywords_nfa.contains(&text));
+    bench("find_iter, literals", 100, || {
+        keywords.find_iter(&text).count()
+    });
+    bench("find_iter, NFA", 100, || {
+        keywords_nfa.find_iter(&text).count()
+    });
+
+    // an ASCII-only machine runs find_end over the bytes of the text
+    let letters = Rejects::new(r"[a-z ]+").unwrap();
+    let chars: Vec<char> = words.chars().collect();
+    bench("find_end, bytes", 100, || letters.find_end(&words));
+    bench("find_end_chars, chars", 100, || {
+        letters.find_end_chars(&chars)
+    });
+
+    // find_first stops at the first match without setting up an iterator
+    let last = Rejects::new(r"elit\s+$").unwrap();
+    bench("find_first", 100, || last.find_first(&words).is_some());
+    bench("find_iter().next()", 100, || {
+        last.find_iter(&words).next().is_some()
+    });
+}
@@ -0,0 +1,158 @@
+use crate::ast::Ast;
+use std::collections::{HashMap, VecDeque};
+
+/// An Aho–Corasick automaton used in place of the NFA when searching for a pattern which is only
+/// a union of plain literals, e.g. `foo|bar|baz`.
+#[derive(Debug)]
+pub(crate) struct AhoCorasick {
+    nodes: Vec<Node>,
+    // the length in bytes of the longest literal
+    max_len: usize,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    next: HashMap<char, usize>,
+    fail: usize,
+    // the length in bytes of the string which leads to this node
+    depth: usize,
+    // the length in bytes of the longest literal ending here, this node's own or one along its
+    // fail links
+    longest: Option<usize>,
+}
+
+impl AhoCorasick {
+    /// Builds the automaton if `ast` is made up only of literals joined by unions, concatenations
+    /// and groups.
+    pub(crate) fn from_ast(ast: &Ast) -> Option<AhoCorasick> {
+        let mut literals = Vec::new();
+        collect_literals(ast, &mut literals)?;
+        Some(AhoCorasick::new(&literals))
+    }
+
+    fn new(literals: &[Vec<char>]) -> AhoCorasick {
+        let mut nodes = vec![Node::default()];
+        for literal in literals {
+            let mut cur = 0;
+            for &c in literal {
+                cur = match nodes[cur].next.get(&c) {
+                    Some(&next) => next,
+                    None => {
+                        let depth = nodes[cur].depth + c.len_utf8();
+                        nodes.push(Node {
+                            depth,
+                            ..Node::default()
+                        });
+                        let next = nodes.len() - 1;
+                        nodes[cur].next.insert(c, next);
+                        next
+                    }
+                };
+            }
+            nodes[cur].longest = Some(nodes[cur].depth);
+        }
+
+        // breadth first so a node's fail link is always finished before its children's
+        let mut queue: VecDeque<usize> = nodes[0].next.values().copied().collect();
+        while let Some(n) = queue.pop_front() {
+            let edges: Vec<(char, usize)> = nodes[n].next.iter().map(|(&c, &m)| (c, m)).collect();
+            for (c, child) in edges {
+                let mut fail = nodes[n].fail;
+                let target = loop {
+                    if let Some(&m) = nodes[fail].next.get(&c) {
+                        break m;
+                    } else if fail == 0 {
+                        break 0;
+                    }
+                    fail = nodes[fail].fail;
+                };
+                nodes[child].fail = target;
+                nodes[child].longest = nodes[child].longest.or(nodes[target].longest);
+                queue.push_back(child);
+            }
+        }
+        let max_len = nodes
+            .iter()
+            .filter_map(|node| node.longest)
+            .max()
+            .unwrap_or(0);
+        AhoCorasick { nodes, max_len }
+    }
+
+    // Follows the edge for `c` from node `cur`, falling back along the fail links.
+    fn next(&self, mut cur: usize, c: char) -> usize {
+        loop {
+            if let Some(&next) = self.nodes[cur].next.get(&c) {
+                return next;
+            } else if cur == 0 {
+                return 0;
+            }
+            cur = self.nodes[cur].fail;
+        }
+    }
+
+    /// returns true if any of the literals occurs in `s`.
+    pub(crate) fn contains(&self, s: &str) -> bool {
+        let mut cur = 0;
+        for c in s.chars() {
+            cur = self.next(cur, c);
+            if self.nodes[cur].longest.is_some() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// returns the byte offset of the leftmost place at or after byte offset `from` where one of
+    /// the literals occurs in `s`.
+    ///
+    /// The literal which ends first doesn't always start first (`abcd|bc` in "abcd"), so the
+    /// scan carries on until no literal ending later could start before the best start so far.
+    pub(crate) fn find_start(&self, s: &str, from: usize) -> Option<usize> {
+        let mut cur = 0;
+        let mut best: Option<usize> = None;
+        for (i, c) in s[from..].char_indices() {
+            let i = from + i;
+            if matches!(best, Some(best) if i >= best + self.max_len) {
+                break;
+            }
+            cur = self.next(cur, c);
+            if let Some(len) = self.nodes[cur].longest {
+                let start = i + c.len_utf8() - len;
+                best = Some(best.map_or(start, |best| best.min(start)));
+            }
+        }
+        best
+    }
+}
+
+fn collect_literals(ast: &Ast, literals: &mut Vec<Vec<char>>) -> Option<()> {
+    match ast {
+        Ast::Union(l, r) => {
+            collect_literals(l, literals)?;
+            collect_literals(r, literals)
+        }
+        Ast::Group(ast) | Ast::NamedGroup(_, ast) => collect_literals(ast, literals),
+        _ => {
+            let mut literal = Vec::new();
+            collect_chars(ast, &mut literal)?;
+            literals.push(literal);
+            Some(())
+        }
+    }
+}
+
+fn collect_chars(ast: &Ast, literal: &mut Vec<char>) -> Option<()> {
+    match ast {
+        Ast::Literal(c) => {
+            literal.push(*c);
+            Some(())
+        }
+        Ast::Concat(l, r) => {
+            collect_chars(l, literal)?;
+            collect_chars(r, literal)
+        }
+        Ast::Group(ast) | Ast::NamedGroup(_, ast) => collect_chars(ast, literal),
+        _ => None,
+    }
+}
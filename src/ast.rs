@@ -0,0 +1,397 @@
+use crate::character_sets;
+use crate::nfa::{Anchor, Fragment, State, StateList};
+#[cfg(feature = "nfc")]
+use crate::rejects::normalize;
+use std::collections::HashSet;
+use std::fmt;
+
+/// The syntax tree the parser produces (see `parse_ast`), which is then compiled into an NFA.
+///
+/// Concatenations and unions are right-nested in the order they are parsed, so `abc` is
+/// `Concat(a, Concat(b, c))`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ast {
+    /// A single character, e.g. `a` or `\*`.
+    Literal(char),
+    /// A single character transition such as `.`, `\w` or `[a-z]`, see `State::Transition` for
+    /// how the two sets are used.
+    Class {
+        inclusive: HashSet<char>,
+        exclusive: HashSet<char>,
+    },
+    /// `^`, `$`, `\z` or `\Z`, which match no characters.
+    Anchor(Anchor),
+    Concat(Box<Ast>, Box<Ast>),
+    /// `l|r`
+    Union(Box<Ast>, Box<Ast>),
+    /// `e*`
+    Star(Box<Ast>),
+    /// `e+`
+    Plus(Box<Ast>),
+    /// `e?`
+    Optional(Box<Ast>),
+    /// `e{min,max}`, or `e{min,}` without a `max`. It is compiled into copies of `e`, so `a{2,4}`
+    /// is compiled as `aa(a(a)?)?` and `a{2,}` as `aa+`.
+    Repeat {
+        ast: Box<Ast>,
+        min: usize,
+        max: Option<usize>,
+    },
+    /// `(e)`
+    Group(Box<Ast>),
+    /// `(?<name>e)`, which can also be written `(?P<name>e)`
+    NamedGroup(String, Box<Ast>),
+}
+
+impl Ast {
+    pub(crate) fn characters(chars: HashSet<char>) -> Ast {
+        Ast::Class {
+            inclusive: chars,
+            exclusive: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn non_characters(chars: HashSet<char>) -> Ast {
+        Ast::Class {
+            inclusive: HashSet::new(),
+            exclusive: chars,
+        }
+    }
+
+    pub(crate) fn union(l: Option<Ast>, r: Option<Ast>) -> Option<Ast> {
+        let l = l?;
+        match r {
+            Some(r) => Some(Ast::Union(Box::new(l), Box::new(r))),
+            None => Some(l),
+        }
+    }
+
+    pub(crate) fn concatenation(l: Option<Ast>, r: Option<Ast>) -> Option<Ast> {
+        let l = l?;
+        match r {
+            Some(r) => Some(Ast::Concat(Box::new(l), Box::new(r))),
+            None => Some(l),
+        }
+    }
+
+    pub(crate) fn unary_operator(ast: Option<Ast>, op: Option<char>) -> Option<Ast> {
+        let ast = Box::new(ast?);
+        match op {
+            Some('*') => Some(Ast::Star(ast)),
+            Some('?') => Some(Ast::Optional(ast)),
+            Some('+') => Some(Ast::Plus(ast)),
+            _ => Some(*ast), // No operand so just return what we have
+        }
+    }
+
+    /// returns the number of characters, classes and anchors in the tree, e.g. 3 for `a(b|c)*`,
+    /// with each `Repeat` counted as the copies it is compiled into, so `(ab){3}` is 6.
+    pub(crate) fn atom_count(&self) -> usize {
+        match self {
+            Ast::Repeat { ast, min, max } => ast.atom_count().saturating_mul(max.unwrap_or(*min)),
+            Ast::Literal(_) | Ast::Class { .. } | Ast::Anchor(_) => 1,
+            Ast::Concat(l, r) | Ast::Union(l, r) => l.atom_count() + r.atom_count(),
+            Ast::Star(ast)
+            | Ast::Plus(ast)
+            | Ast::Optional(ast)
+            | Ast::Group(ast)
+            | Ast::NamedGroup(_, ast) => ast.atom_count(),
+        }
+    }
+
+    /// returns the tree with its characters in NFC, see `Options::nfc`. Each run of literals is
+    /// normalized as one string, so `e\u{301}` becomes `é` but `e\u{301}*` is left as it is, and
+    /// each member of a class is normalized on its own, staying as it is if that doesn't give a
+    /// single character.
+    #[cfg(feature = "nfc")]
+    pub(crate) fn into_nfc(self) -> Ast {
+        fn concat_parts(ast: Ast, parts: &mut Vec<Ast>) {
+            match ast {
+                Ast::Concat(l, r) => {
+                    concat_parts(*l, parts);
+                    concat_parts(*r, parts);
+                }
+                ast => parts.push(ast),
+            }
+        }
+        fn nfc_set(set: HashSet<char>) -> HashSet<char> {
+            set.into_iter()
+                .map(|c| {
+                    let mut buf = [0; 4];
+                    let normalized = normalize(c.encode_utf8(&mut buf), true);
+                    let mut chars = normalized.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(n), None) => n,
+                        _ => c,
+                    }
+                })
+                .collect()
+        }
+        let boxed = |ast: Box<Ast>| Box::new(ast.into_nfc());
+
+        let mut parts = Vec::new();
+        concat_parts(self, &mut parts);
+        let mut normalized = Vec::new();
+        let mut run = String::new();
+        for part in parts {
+            if let Ast::Literal(c) = part {
+                run.push(c);
+                continue;
+            }
+            normalized.extend(normalize(&run, true).chars().map(Ast::Literal));
+            run.clear();
+            normalized.push(match part {
+                Ast::Class {
+                    inclusive,
+                    exclusive,
+                } => Ast::Class {
+                    inclusive: nfc_set(inclusive),
+                    exclusive: nfc_set(exclusive),
+                },
+                Ast::Union(l, r) => Ast::Union(boxed(l), boxed(r)),
+                Ast::Star(ast) => Ast::Star(boxed(ast)),
+                Ast::Plus(ast) => Ast::Plus(boxed(ast)),
+                Ast::Optional(ast) => Ast::Optional(boxed(ast)),
+                Ast::Repeat { ast, min, max } => Ast::Repeat {
+                    ast: boxed(ast),
+                    min,
+                    max,
+                },
+                Ast::Group(ast) => Ast::Group(boxed(ast)),
+                Ast::NamedGroup(name, ast) => Ast::NamedGroup(name, boxed(ast)),
+                part => part,
+            });
+        }
+        normalized.extend(normalize(&run, true).chars().map(Ast::Literal));
+        normalized
+            .into_iter()
+            .rev()
+            .fold(None, |r, l| Ast::concatenation(Some(l), r))
+            .expect("the tree has at least one part")
+    }
+
+    /// Compiles the tree into an NFA, returning the start state and the state list.
+    pub(crate) fn to_nfa(&self) -> (usize, Vec<State>) {
+        let mut statelist = StateList::new();
+        let frag = self.compile(&mut statelist);
+        let match_state = statelist.add_state(State::make_match());
+        for &dangler in frag.endstates.iter() {
+            statelist.link(dangler, match_state);
+        }
+        (frag.start, statelist.states)
+    }
+
+    pub(crate) fn compile(&self, statelist: &mut StateList) -> Fragment {
+        match self {
+            Ast::Literal(c) => statelist.character(*c),
+            Ast::Class {
+                inclusive,
+                exclusive,
+            } => statelist.inclusive_exclusive_characters(inclusive.clone(), exclusive.clone()),
+            Ast::Anchor(anchor) => statelist.anchor(*anchor),
+            Ast::Concat(l, r) => {
+                let l = l.compile(statelist);
+                let r = r.compile(statelist);
+                statelist.concatenation(l, r)
+            }
+            Ast::Union(l, r) => {
+                let l = l.compile(statelist);
+                let r = r.compile(statelist);
+                statelist.union(l, r)
+            }
+            Ast::Star(ast) => {
+                let f = ast.compile(statelist);
+                statelist.kleene(f)
+            }
+            Ast::Plus(ast) => {
+                let f = ast.compile(statelist);
+                statelist.plus(f)
+            }
+            Ast::Optional(ast) => {
+                let f = ast.compile(statelist);
+                statelist.question_mark(f)
+            }
+            Ast::Repeat { ast, min, max } => {
+                let (required, mut rest) = match max {
+                    None => {
+                        let f = ast.compile(statelist);
+                        if *min == 0 {
+                            (0, Some(statelist.kleene(f)))
+                        } else {
+                            (min - 1, Some(statelist.plus(f)))
+                        }
+                    }
+                    Some(max) => {
+                        // each optional copy is only tried after the ones before it
+                        let mut rest = None;
+                        for _ in *min..*max {
+                            let f = ast.compile(statelist);
+                            let f = match rest {
+                                Some(rest) => statelist.concatenation(f, rest),
+                                None => f,
+                            };
+                            rest = Some(statelist.question_mark(f));
+                        }
+                        (*min, rest)
+                    }
+                };
+                for _ in 0..required {
+                    let f = ast.compile(statelist);
+                    rest = Some(match rest {
+                        Some(rest) => statelist.concatenation(f, rest),
+                        None => f,
+                    });
+                }
+                // the parser doesn't allow `e{0}`
+                rest.expect("a repetition has at least one copy")
+            }
+            Ast::Group(ast) | Ast::NamedGroup(_, ast) => ast.compile(statelist),
+        }
+    }
+
+    fn is_atom(&self) -> bool {
+        matches!(
+            self,
+            Ast::Literal(_)
+                | Ast::Class { .. }
+                | Ast::Anchor(_)
+                | Ast::Group(_)
+                | Ast::NamedGroup(_, _)
+        )
+    }
+}
+
+/// Prints a canonical pattern which parses back into an equivalent tree. Parentheses are only
+/// added where the tree's shape needs them and wasn't already grouped.
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ast::Literal(c) => fmt_literal(f, *c),
+            Ast::Class {
+                inclusive,
+                exclusive,
+            } => fmt_class(f, inclusive, exclusive),
+            Ast::Anchor(Anchor::Start) | Ast::Anchor(Anchor::LineStart) => write!(f, "^"),
+            Ast::Anchor(Anchor::End) | Ast::Anchor(Anchor::LineEnd) => write!(f, "$"),
+            Ast::Anchor(Anchor::EndBeforeNewline) => write!(f, "\\Z"),
+            Ast::Concat(l, r) => {
+                fmt_concat_operand(f, l)?;
+                fmt_concat_operand(f, r)
+            }
+            Ast::Union(l, r) => write!(f, "{}|{}", l, r),
+            Ast::Star(ast) => fmt_unary(f, ast, '*'),
+            Ast::Plus(ast) => fmt_unary(f, ast, '+'),
+            Ast::Optional(ast) => fmt_unary(f, ast, '?'),
+            Ast::Repeat { ast, min, max } => {
+                if ast.is_atom() {
+                    write!(f, "{}", ast)?;
+                } else {
+                    write!(f, "({})", ast)?;
+                }
+                match max {
+                    Some(max) if max == min => write!(f, "{{{}}}", min),
+                    Some(max) => write!(f, "{{{},{}}}", min, max),
+                    None => write!(f, "{{{},}}", min),
+                }
+            }
+            Ast::Group(ast) => write!(f, "({})", ast),
+            Ast::NamedGroup(name, ast) => write!(f, "(?<{}>{})", name, ast),
+        }
+    }
+}
+
+fn fmt_concat_operand(f: &mut fmt::Formatter, ast: &Ast) -> fmt::Result {
+    if let Ast::Union(_, _) = ast {
+        write!(f, "({})", ast)
+    } else {
+        write!(f, "{}", ast)
+    }
+}
+
+fn fmt_unary(f: &mut fmt::Formatter, ast: &Ast, op: char) -> fmt::Result {
+    if ast.is_atom() {
+        write!(f, "{}{}", ast, op)
+    } else {
+        write!(f, "({}){}", ast, op)
+    }
+}
+
+fn fmt_literal(f: &mut fmt::Formatter, c: char) -> fmt::Result {
+    match c {
+        '\\' | '*' | '+' | '(' | ')' | '.' | '[' | '{' | '^' | '$' => write!(f, "\\{}", c),
+        // these have no escape outside of a class
+        '?' | '|' => write!(f, "[{}]", c),
+        _ => write!(f, "{}", c),
+    }
+}
+
+fn fmt_class(
+    f: &mut fmt::Formatter,
+    inclusive: &HashSet<char>,
+    exclusive: &HashSet<char>,
+) -> fmt::Result {
+    match (inclusive.is_empty(), exclusive.is_empty()) {
+        // '.' is only this when Options::dot_excludes is empty
+        (true, true) => write!(f, "[\\s\\S]"),
+        (false, true) => match shorthand(inclusive, false) {
+            Some(s) => write!(f, "{}", s),
+            None if inclusive.len() == 1 => fmt_literal(f, *inclusive.iter().next().unwrap()),
+            None => write!(f, "[{}]", members(inclusive)),
+        },
+        (true, false) => match shorthand(exclusive, true) {
+            Some(s) => write!(f, "{}", s),
+            None if exclusive.len() == 1 && exclusive.contains(&'\n') => write!(f, "."),
+            None => write!(f, "[^{}]", members(exclusive)),
+        },
+        (false, false) => {
+            write!(f, "[{}", members(inclusive))?;
+            let sets = [
+                (character_sets::word_chars(), "\\W"),
+                (character_sets::digits(), "\\D"),
+                (character_sets::whitespace(), "\\S"),
+            ];
+            for (set, s) in sets.iter() {
+                if set.is_subset(exclusive) {
+                    write!(f, "{}", s)?;
+                }
+            }
+            write!(f, "]")
+        }
+    }
+}
+
+fn shorthand(chars: &HashSet<char>, negated: bool) -> Option<&'static str> {
+    let (w, d, s) = if negated {
+        ("\\W", "\\D", "\\S")
+    } else {
+        ("\\w", "\\d", "\\s")
+    };
+    if *chars == character_sets::word_chars() {
+        Some(w)
+    } else if *chars == character_sets::digits() {
+        Some(d)
+    } else if *chars == character_sets::whitespace() {
+        Some(s)
+    } else {
+        None
+    }
+}
+
+// The members of a class in sorted order, with a '-' first so it isn't read as a range and a '^'
+// last so it isn't read as a negation.
+fn members(chars: &HashSet<char>) -> String {
+    let mut sorted: Vec<char> = chars.iter().copied().collect();
+    sorted.sort_unstable_by_key(|&c| match c {
+        '-' => (0, c),
+        '^' => (2, c),
+        _ => (1, c),
+    });
+    let mut s = String::new();
+    for c in sorted {
+        if c == ']' || c == '\\' {
+            s.push('\\');
+        }
+        s.push(c);
+    }
+    s
+}
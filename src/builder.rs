@@ -1,10 +1,15 @@
-use crate::nfa::State;
+use crate::nfa::{Anchor, State};
+use crate::options::MatchKind;
 use crate::rejects::Rejects;
 use std::collections::HashSet;
 
 pub struct Builder {
     start: usize,
     statelist: Vec<State>,
+    match_kind: MatchKind,
+    anchored: bool,
+    #[cfg(feature = "nfc")]
+    nfc: bool,
 }
 
 impl Builder {
@@ -12,11 +17,41 @@ impl Builder {
         Builder {
             start,
             statelist: Vec::new(),
+            match_kind: MatchKind::LeftmostLongest,
+            anchored: false,
+            #[cfg(feature = "nfc")]
+            nfc: false,
         }
     }
 
     pub fn build(self) -> Rejects {
-        Rejects::from(self.start, self.statelist)
+        let mut rejects = Rejects::from(self.start, self.statelist);
+        rejects.match_kind = self.match_kind;
+        rejects.anchored = self.anchored;
+        #[cfg(feature = "nfc")]
+        {
+            rejects.nfc = self.nfc;
+        }
+        rejects
+    }
+
+    // The Options which are kept on the machine rather than compiled into its states, so what
+    // ToTokens emits can set them again.
+
+    pub fn match_kind(&mut self, match_kind: MatchKind) -> &Builder {
+        self.match_kind = match_kind;
+        self
+    }
+
+    pub fn anchored(&mut self, anchored: bool) -> &Builder {
+        self.anchored = anchored;
+        self
+    }
+
+    #[cfg(feature = "nfc")]
+    pub fn nfc(&mut self, nfc: bool) -> &Builder {
+        self.nfc = nfc;
+        self
     }
 
     pub fn add_state(&mut self, state: State) -> &Builder {
@@ -43,6 +78,11 @@ impl Builder {
         self
     }
 
+    pub fn with_anchor(&mut self, anchor: Anchor, out: Option<usize>) -> &Builder {
+        self.statelist.push(State::Anchor { anchor, out });
+        self
+    }
+
     pub fn with_match(&mut self) -> &Builder {
         self.statelist.push(State::Match);
         self
@@ -0,0 +1,95 @@
+use crate::parser::ParseError;
+use crate::rejects::Rejects;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A least recently used cache of compiled patterns, keyed by the pattern string.
+///
+/// Compiling the same pattern twice with `RejectsCache::get` only parses it once, the second
+/// lookup hands back a shared handle to the already compiled machine. Patterns which fail to
+/// compile are not cached.
+#[derive(Debug)]
+pub struct RejectsCache {
+    capacity: usize,
+    // each pattern with when it was last used
+    entries: HashMap<String, (Arc<Rejects>, u64)>,
+    // the patterns by when they were last used, least recently used first
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    hits: usize,
+    misses: usize,
+}
+
+impl RejectsCache {
+    pub fn new(capacity: usize) -> RejectsCache {
+        RejectsCache {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// returns the compiled machine for `pat`, compiling and caching it if it isn't cached.
+    pub fn get(&mut self, pat: &str) -> Result<Arc<Rejects>, Vec<ParseError>> {
+        self.tick += 1;
+        if let Some((rejects, used)) = self.entries.get_mut(pat) {
+            self.hits += 1;
+            if let Some(p) = self.order.remove(used) {
+                self.order.insert(self.tick, p);
+            }
+            *used = self.tick;
+            return Ok(Arc::clone(rejects));
+        }
+
+        self.misses += 1;
+        let rejects = Arc::new(Rejects::new(pat)?);
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                if let Some((_, lru)) = self.order.pop_first() {
+                    self.entries.remove(&lru);
+                }
+            }
+            self.entries
+                .insert(pat.to_string(), (Arc::clone(&rejects), self.tick));
+            self.order.insert(self.tick, pat.to_string());
+        }
+        Ok(rejects)
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// how many patterns compile_cached keeps
+const GLOBAL_CAPACITY: usize = 256;
+
+static GLOBAL: OnceLock<Mutex<RejectsCache>> = OnceLock::new();
+
+/// Compiles `pat` like `Rejects::new`, sharing the compiled machine through a cache global to the
+/// process so the same pattern compiled anywhere, on any thread, is only parsed once (while it
+/// stays among the 256 most recently used patterns).
+///
+/// The cache is locked while a pattern is compiled, so threads compiling different patterns at
+/// the same time wait on each other. Use a `RejectsCache` of your own to avoid that.
+pub fn compile_cached(pat: &str) -> Result<Arc<Rejects>, Vec<ParseError>> {
+    let cache = GLOBAL.get_or_init(|| Mutex::new(RejectsCache::new(GLOBAL_CAPACITY)));
+    // a panic while compiling can't leave the cache half updated, so a poisoned lock is fine
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    cache.get(pat)
+}
@@ -27,16 +27,38 @@ pub(crate) fn whitespace() -> HashSet<char> {
     let mut set = HashSet::new();
     set.insert(' ');
     set.insert('\t');
+    set.insert('\n');
+    set.insert('\r');
+    set.insert('\x0c');
+    set.insert('\x0b');
     set
 }
 
-pub(crate) fn range(low: u8, high: u8) -> Result<HashSet<char>, (u8, u8)> {
+/// The ASCII characters of the POSIX class `[:name:]`, or `None` if there is no such class.
+pub(crate) fn posix_class(name: &str) -> Option<HashSet<char>> {
+    let class: fn(&char) -> bool = match name {
+        "alpha" => |c| c.is_ascii_alphabetic(),
+        "digit" => |c| c.is_ascii_digit(),
+        "alnum" => |c| c.is_ascii_alphanumeric(),
+        "upper" => |c| c.is_ascii_uppercase(),
+        "lower" => |c| c.is_ascii_lowercase(),
+        "space" => |c| matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0B' | '\x0C'),
+        "blank" => |c| matches!(c, ' ' | '\t'),
+        "punct" => |c| c.is_ascii_punctuation(),
+        "xdigit" => |c| c.is_ascii_hexdigit(),
+        "cntrl" => |c| c.is_ascii_control(),
+        "graph" => |c| c.is_ascii_graphic(),
+        "print" => |c| c.is_ascii_graphic() || *c == ' ',
+        "word" => |c| c.is_ascii_alphanumeric() || *c == '_',
+        "ascii" => |_| true,
+        _ => return None,
+    };
+    Some((0..=127u8).map(char::from).filter(class).collect())
+}
+
+pub(crate) fn range(low: char, high: char) -> Result<HashSet<char>, (char, char)> {
     if high < low {
         return Err((low, high));
     }
-    let mut set = HashSet::new();
-    for c in low..high {
-        set.insert(c as char);
-    }
-    Ok(set)
+    Ok((low..=high).collect())
 }
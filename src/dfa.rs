@@ -0,0 +1,447 @@
+use crate::nfa::State;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+/// A deterministic automaton in dense table form, which can be imported with
+/// `Rejects::from_dfa`.
+///
+/// States are numbered `0..table.len()`. Each row of `table` has one entry per character of
+/// `alphabet`: `table[state][i]` is the state reached from `state` on `alphabet[i]`, or `None` if
+/// there is no such transition. Characters outside of `alphabet` have no transitions. The machine
+/// begins in `start` and accepts when it is in any of the `accepting` states.
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    pub start: usize,
+    pub alphabet: Vec<char>,
+    pub table: Vec<Vec<Option<usize>>>,
+    pub accepting: HashSet<usize>,
+}
+
+impl Dfa {
+    /// returns the index of the first state which is out of range or has a malformed row.
+    pub(crate) fn validate(&self) -> Result<(), usize> {
+        let len = self.table.len();
+        if self.start >= len {
+            return Err(self.start);
+        }
+        for (n, row) in self.table.iter().enumerate() {
+            if row.len() != self.alphabet.len() || row.iter().flatten().any(|&out| out >= len) {
+                return Err(n);
+            }
+        }
+        match self.accepting.iter().find(|&&n| n >= len) {
+            Some(&n) => Err(n),
+            None => Ok(()),
+        }
+    }
+
+    /// Lowers the table into NFA states, returning the start state and the state list.
+    pub(crate) fn to_states(&self) -> (usize, Vec<State>) {
+        lower(self, &vec![None; self.table.len()])
+    }
+}
+
+/// The subset construction was stopped because the DFA would have more than `limit` states,
+/// see `Rejects::nfa_to_dfa_states_estimate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooLarge {
+    pub limit: usize,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DFA would have more than {} states", self.limit)
+    }
+}
+
+/// A `Dfa` which also has a transition for the characters outside of its alphabet, this is what
+/// determinizing a machine with negated classes or '.' in it gives.
+#[derive(Debug, Clone)]
+pub(crate) struct Determinized {
+    pub(crate) dfa: Dfa,
+    // other[state] is the state reached on any character not in dfa.alphabet
+    pub(crate) other: Vec<Option<usize>>,
+}
+
+impl Determinized {
+    /// Runs the subset construction over the NFA `states` starting at `start`.
+    ///
+    /// Transitions into states which can never reach a `Match` are dropped, so the result only
+    /// has live states (other than the start, which is kept even when nothing is matched).
+    pub(crate) fn from_nfa(start: usize, states: &[State]) -> Determinized {
+        match Determinized::from_nfa_with_limit(start, states, usize::MAX) {
+            Ok(determinized) => determinized,
+            Err(_) => unreachable!("there can't be more than usize::MAX subsets"),
+        }
+    }
+
+    /// Same as `from_nfa` but gives up once more than `limit` subsets have been found.
+    pub(crate) fn from_nfa_with_limit(
+        start: usize,
+        states: &[State],
+        limit: usize,
+    ) -> Result<Determinized, TooLarge> {
+        let mut alphabet = BTreeSet::new();
+        for state in states {
+            if let State::Transition {
+                inclusive,
+                exclusive,
+                ..
+            } = state
+            {
+                alphabet.extend(inclusive);
+                alphabet.extend(exclusive);
+            }
+        }
+        let alphabet: Vec<char> = alphabet.into_iter().collect();
+
+        let mut subsets = vec![closure(states, vec![start])];
+        let mut index: HashMap<BTreeSet<usize>, usize> = HashMap::new();
+        index.insert(subsets[0].clone(), 0);
+        let mut table = Vec::new();
+        let mut other = Vec::new();
+        let mut accepting = HashSet::new();
+        let mut n = 0;
+        while n < subsets.len() {
+            if subsets[n]
+                .iter()
+                .any(|&s| matches!(states[s], State::Match))
+            {
+                accepting.insert(n);
+            }
+            // the columns are the alphabet in order and then the characters outside of it
+            let mut row = Vec::with_capacity(alphabet.len() + 1);
+            for c in alphabet.iter().map(Some).chain(std::iter::once(None)) {
+                let outs = subsets[n]
+                    .iter()
+                    .filter_map(|&s| match c {
+                        Some(c) => states[s].transition(*c),
+                        None => other_transition(&states[s]),
+                    })
+                    .collect();
+                let subset = closure(states, outs);
+                row.push(if subset.is_empty() {
+                    None
+                } else if let Some(&m) = index.get(&subset) {
+                    Some(m)
+                } else if subsets.len() == limit {
+                    return Err(TooLarge { limit });
+                } else {
+                    index.insert(subset.clone(), subsets.len());
+                    subsets.push(subset);
+                    Some(subsets.len() - 1)
+                });
+            }
+            other.push(row.pop().unwrap());
+            table.push(row);
+            n += 1;
+        }
+
+        let mut determinized = Determinized {
+            dfa: Dfa {
+                start: 0,
+                alphabet,
+                table,
+                accepting,
+            },
+            other,
+        };
+        determinized.prune();
+        Ok(determinized)
+    }
+
+    /// Minimizes the machine with Hopcroft's algorithm, merging every set of states which accept
+    /// the same strings into one.
+    ///
+    /// Expects every state but the start to be live, as `from_nfa` leaves them.
+    pub(crate) fn minimize(&self) -> Determinized {
+        let len = self.dfa.table.len();
+        let columns = self.dfa.alphabet.len() + 1;
+        // state `len` is an implicit dead state which every missing transition goes to
+        let dead = len;
+        let target = |n: usize, c: usize| -> usize {
+            if n == dead {
+                dead
+            } else if c == columns - 1 {
+                self.other[n].unwrap_or(dead)
+            } else {
+                self.dfa.table[n][c].unwrap_or(dead)
+            }
+        };
+        let mut inverse = vec![vec![Vec::new(); len + 1]; columns];
+        for n in 0..=len {
+            for (c, inverse) in inverse.iter_mut().enumerate() {
+                inverse[target(n, c)].push(n);
+            }
+        }
+
+        let (accepting, rejecting): (Vec<usize>, Vec<usize>) =
+            (0..=len).partition(|n| self.dfa.accepting.contains(n));
+        let mut blocks: Vec<Vec<usize>> = vec![accepting, rejecting]
+            .into_iter()
+            .filter(|block| !block.is_empty())
+            .collect();
+        let mut block_of = vec![0; len + 1];
+        for (b, block) in blocks.iter().enumerate() {
+            for &n in block {
+                block_of[n] = b;
+            }
+        }
+        let mut worklist: Vec<usize> = (0..blocks.len()).collect();
+        let mut queued = vec![true; blocks.len()];
+
+        while let Some(b) = worklist.pop() {
+            queued[b] = false;
+            // the splitter may itself be split below, keep splitting on all of it
+            let splitter = blocks[b].clone();
+            for inverse in &inverse {
+                let preds: HashSet<usize> = splitter
+                    .iter()
+                    .flat_map(|&n| inverse[n].iter().copied())
+                    .collect();
+                let touched: BTreeSet<usize> = preds.iter().map(|&n| block_of[n]).collect();
+                for b in touched {
+                    let (inside, outside): (Vec<usize>, Vec<usize>) =
+                        blocks[b].iter().partition(|n| preds.contains(n));
+                    if outside.is_empty() {
+                        continue;
+                    }
+                    let split = blocks.len();
+                    for &n in &outside {
+                        block_of[n] = split;
+                    }
+                    let smaller = if inside.len() <= outside.len() {
+                        b
+                    } else {
+                        split
+                    };
+                    blocks[b] = inside;
+                    blocks.push(outside);
+                    queued.push(false);
+                    // if b is still to be split on then both halves must be, otherwise splitting
+                    // on the smaller half is enough
+                    if queued[b] {
+                        worklist.push(split);
+                        queued[split] = true;
+                    } else {
+                        worklist.push(smaller);
+                        queued[smaller] = true;
+                    }
+                }
+            }
+        }
+
+        // number the merged states in order of their lowest member, without the dead state's
+        // block unless the start is in it
+        let mut renumber = vec![None; blocks.len()];
+        let mut members = Vec::new();
+        for n in 0..len {
+            let b = block_of[n];
+            if renumber[b].is_none() && (b != block_of[dead] || n == self.dfa.start) {
+                renumber[b] = Some(members.len());
+                members.push(n);
+            }
+        }
+        let state = |n: Option<usize>| {
+            n.filter(|&n| block_of[n] != block_of[dead])
+                .and_then(|n| renumber[block_of[n]])
+        };
+        Determinized {
+            dfa: Dfa {
+                start: renumber[block_of[self.dfa.start]].unwrap(),
+                alphabet: self.dfa.alphabet.clone(),
+                table: members
+                    .iter()
+                    .map(|&n| self.dfa.table[n].iter().map(|&out| state(out)).collect())
+                    .collect(),
+                accepting: members
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, n)| self.dfa.accepting.contains(n))
+                    .map(|(m, _)| m)
+                    .collect(),
+            },
+            other: members.iter().map(|&n| state(self.other[n])).collect(),
+        }
+    }
+
+    pub(crate) fn to_states(&self) -> (usize, Vec<State>) {
+        lower(&self.dfa, &self.other)
+    }
+
+    // Removes the transitions into states that can't reach an accepting state, along with every
+    // state which is then unreachable from the start.
+    fn prune(&mut self) {
+        let len = self.dfa.table.len();
+        let mut preds = vec![Vec::new(); len];
+        for (n, row) in self.dfa.table.iter().enumerate() {
+            for &out in row.iter().chain(std::iter::once(&self.other[n])).flatten() {
+                preds[out].push(n);
+            }
+        }
+        let mut live = vec![false; len];
+        let mut stack: Vec<usize> = self.dfa.accepting.iter().copied().collect();
+        while let Some(n) = stack.pop() {
+            if !live[n] {
+                live[n] = true;
+                stack.extend(preds[n].iter().copied());
+            }
+        }
+
+        let mut renumber = vec![None; len];
+        let mut order = vec![self.dfa.start];
+        renumber[self.dfa.start] = Some(0);
+        let mut i = 0;
+        while i < order.len() {
+            let n = order[i];
+            for &out in self.dfa.table[n]
+                .iter()
+                .chain(std::iter::once(&self.other[n]))
+                .flatten()
+            {
+                if live[out] && renumber[out].is_none() {
+                    renumber[out] = Some(order.len());
+                    order.push(out);
+                }
+            }
+            i += 1;
+        }
+        let state = |out: &Option<usize>| out.and_then(|out| renumber[out]);
+        self.dfa.table = order
+            .iter()
+            .map(|&n| self.dfa.table[n].iter().map(state).collect())
+            .collect();
+        self.other = order.iter().map(|&n| state(&self.other[n])).collect();
+        self.dfa.accepting = order
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| self.dfa.accepting.contains(n))
+            .map(|(m, _)| m)
+            .collect();
+        self.dfa.start = 0;
+    }
+}
+
+// The states `states` can get to from `from` without reading a character, including `from`. An
+// anchor is passed through as if it held, there is no knowing where in the input a DFA state is.
+fn closure(states: &[State], from: Vec<usize>) -> BTreeSet<usize> {
+    let mut closure = BTreeSet::new();
+    let mut stack = from;
+    while let Some(n) = stack.pop() {
+        if closure.insert(n) {
+            if let State::Split { .. } | State::Anchor { .. } = states[n] {
+                stack.extend(states[n].outs());
+            }
+        }
+    }
+    closure
+}
+
+// The state `state` moves to on a character which isn't in any of the machine's sets.
+fn other_transition(state: &State) -> Option<usize> {
+    match state {
+        State::Transition {
+            inclusive,
+            exclusive,
+            out,
+        } if !exclusive.is_empty() || inclusive.is_empty() => *out,
+        _ => None,
+    }
+}
+
+/// Lowers a DFA table into NFA states, returning the start state and the state list. `other` has
+/// the transition of each state on characters outside of the alphabet.
+///
+/// Every DFA state becomes one `Transition` per distinct target (with all characters leading to
+/// that target in its inclusive set, or all the other characters in its exclusive set when it's
+/// the target of `other`), plus an edge to a shared `Match` state when it is accepting, joined
+/// together by a chain of `Split`s. A state without any edges becomes `Nil`.
+fn lower(dfa: &Dfa, other: &[Option<usize>]) -> (usize, Vec<State>) {
+    let targets: Vec<BTreeMap<usize, HashSet<char>>> = dfa
+        .table
+        .iter()
+        .map(|row| {
+            let mut targets = BTreeMap::new();
+            for (&c, out) in dfa.alphabet.iter().zip(row.iter()) {
+                if let Some(out) = out {
+                    targets.entry(*out).or_insert_with(HashSet::new).insert(c);
+                }
+            }
+            targets
+        })
+        .collect();
+    let targets: Vec<Vec<(usize, State)>> = targets
+        .into_iter()
+        .zip(other)
+        .map(|(mut targets, other)| {
+            let mut transitions = Vec::new();
+            if let Some(other) = *other {
+                let chars = targets.remove(&other).unwrap_or_default();
+                let exclusive = dfa
+                    .alphabet
+                    .iter()
+                    .filter(|c| !chars.contains(c))
+                    .copied()
+                    .collect();
+                transitions.push((other, State::make_exclusive_transition(exclusive)));
+            }
+            for (out, chars) in targets {
+                transitions.push((out, State::make_inclusive_transition(chars)));
+            }
+            transitions.sort_by_key(|(out, _)| *out);
+            transitions
+        })
+        .collect();
+
+    // the Match state is 0, then each DFA state gets a block of its transitions followed by its
+    // splits (or a single Nil)
+    let mut bases = Vec::with_capacity(targets.len());
+    let mut entries = Vec::with_capacity(targets.len());
+    let mut next = 1;
+    for (n, t) in targets.iter().enumerate() {
+        let transitions = t.len();
+        let options = transitions + dfa.accepting.contains(&n) as usize;
+        let splits = options.saturating_sub(1);
+        bases.push(next);
+        entries.push(if options == 0 {
+            next
+        } else if splits > 0 {
+            next + transitions
+        } else if transitions > 0 {
+            next
+        } else {
+            0
+        });
+        next += if options == 0 {
+            1
+        } else {
+            transitions + splits
+        };
+    }
+
+    let mut states = vec![State::make_match()];
+    for (n, t) in targets.into_iter().enumerate() {
+        let mut options: Vec<usize> = (bases[n]..bases[n] + t.len()).collect();
+        if dfa.accepting.contains(&n) {
+            options.push(0);
+        }
+        if options.is_empty() {
+            states.push(State::make_nil());
+            continue;
+        }
+        for (out, mut transition) in t {
+            transition.set_out(entries[out]);
+            states.push(transition);
+        }
+        let splits = options.len() - 1;
+        for (i, &option) in options.iter().take(splits).enumerate() {
+            let rest = if i + 1 == splits {
+                options[i + 1]
+            } else {
+                states.len() + 1
+            };
+            states.push(State::make_split(option, Some(rest)));
+        }
+    }
+    (entries[dfa.start], states)
+}
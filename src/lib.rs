@@ -3,32 +3,94 @@ use proc_macro_hack::proc_macro_hack;
 #[proc_macro_hack]
 pub use rejects_macro::make_rejects;
 
+pub use crate::cache::compile_cached;
+pub use crate::parser::{parse_ast, ParseError, ParseErrorKind, Warning, WarningKind};
+
+mod aho_corasick;
+pub mod ast;
 pub mod builder;
+pub mod cache;
 mod character_sets;
+pub mod dfa;
+pub mod matcher;
+pub mod matches;
 pub mod nfa;
+mod optimize;
+pub mod options;
 mod parser;
+#[cfg(feature = "serde")]
+mod persist;
 pub mod rejects;
+pub mod replace;
+pub mod set;
+pub mod table;
 
 /// Rejects is an implementation of regular expressions that implements the following:
 ///     '*': Zero or more on the preceding (based on operator precedence) regular expression.
 ///     '+': One or more on the preceding (based on operator precedence) regular expression.
 ///     '?': Zero or One on the preceding (based on operator precedence) regular expression.
+///     '{n}', '{n,}', '{n,m}': Exactly n, at least n, or between n and m (inclusive) of the
+///                             preceding regular expression. A count of 0 (e.g. 'a{0}') is an
+///                             error, and a '{' which doesn't follow anything is the character
+///                             itself. How large a repetition can be is limited by
+///                             `Options::max_repetition`.
 ///     '|': For union of multiple regular expressions.
 ///     '()': For precedence only, referring to capture groups with \1 is not yet supported.
+///     '(?i)': Matches the rest of the enclosing group (or pattern) case insensitively, '(?-i)'
+///             turns this back off and '(?i:...)' only applies to the group it starts.
+///     '(?<name>)': A named group, also written '(?P<name>)'. The name is kept in the syntax tree
+///                  but the group is otherwise the same as '()'.
 ///     '[]': For union of various characters, character ranges over ascii characters (e.g. a-z, 0-9, A-Z),
-///           character sets ('\w', '\W', '\d', '\D', '\s', '\S'), '\' is supported by escaping it ('\\').
+///           character sets ('\w', '\W', '\d', '\D', '\s', '\S'), POSIX classes over ascii characters
+///           (e.g. [[:alpha:]], or negated [[:^alpha:]]), '\' is supported by escaping it ('\\').
+///           A class can end with a class to subtract from it (e.g. [a-z-[aeiou]]). A ']' right
+///           after the '[' (or '[^') is a member of the class, so []a] matches ']' or 'a'.
 ///           The entire block can be negated using '^' at the start (e.g. [^a-z] to match anything
 ///           except [a-z]).
-///     '.': Any character.
+///     '.': Any character except a newline, this can be changed with `Options::dot_excludes`.
+///     '^', '$': The start and the end of the input, these match no characters (e.g. '^abc$'
+///               only matches "abc" itself, wherever the search starts). With
+///               `Options::multiline` they also match after and before each '\n'.
 ///     '\': Denotes the following character to be special. Special characters are members of the
-///          set {'w', 'W', 'd', 'D', 's', 'S', '*', '+', '\', '(', ')', '.'}. They work as
-///          expected based on PCRE2.
+///          set {'w', 'W', 'd', 'D', 's', 'S', '*', '+', '\', '(', ')', '.', '[', ']', '{', '}', '^',
+///          '$'}.
+///          They work as expected based on PCRE2. '\n', '\r', '\t' and '\f' are the control
+///          characters, these and '\w' through '\S' can also be used in a class (e.g. [^\n] for
+///          any character but a newline).
+///     '\N{NAME}': The character with the unicode name NAME (e.g. \N{BULLET}), this needs the
+///                 `unicode-names` feature.
+///
+/// Searches report the longest of the matches starting at the leftmost position they can, where
+/// PCRE2 (a backtracking engine) reports the first one it finds: it tries the alternatives of a
+/// '|' from left to right and lets '*', '+' and '?' take as much as they can, taking the first
+/// way the rest of the pattern then matches. So `a|ab` finds "ab" in "ab" here but "a" in PCRE2,
+/// and `(a|ab)(c|b)` finds "abc" in "abc" here but "ab" in PCRE2. Whether a string matches at all
+/// doesn't depend on this, only which match is reported does. Compile with
+/// `MatchKind::LeftmostFirst` (see `Options::match_kind`) to get the match PCRE2 would.
 ///
 /// The grammar is explained in parser.rs.
 
 #[cfg(test)]
 mod tests {
+    use crate::ast::Ast;
+    use crate::builder::Builder;
+    use crate::cache::RejectsCache;
+    use crate::character_sets;
+    use crate::dfa::{Dfa, TooLarge};
+    use crate::nfa::{Anchor, State, StateVisitor};
+    use crate::options::{MatchKind, Options};
     use crate::parser;
+    use crate::rejects::{Rejects, Timeout, TooManyActive};
+    use crate::replace::{NoExpand, ReplaceError, ReplaceErrorKind};
+    use crate::set::RejectsSet;
+    use crate::table::{StaticRejects, StaticState};
+    use crate::{parse_ast, ParseError, ParseErrorKind, Warning, WarningKind};
+    use std::collections::{HashMap, HashSet};
+    use std::ffi::OsStr;
+    use std::io::{BufReader, Cursor};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
 
     #[test]
     fn test_valid_regexes() {
@@ -46,6 +108,7 @@ mod tests {
             r"(\w)",
             r"\\",
             r"[1-9]",
+            r"\[abc\]",
         ];
         for regex in regexes {
             assert!(
@@ -81,6 +144,2499 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_debug_assert_valid() {
+        for regex in &[
+            r"abcd",
+            r"a|b+(c?|d)",
+            r"(abc)(abc)(abc)|(abc)(abc)",
+            r"[1-9]",
+        ] {
+            Rejects::new(regex).unwrap().debug_assert_valid();
+        }
+
+        let mut builder = Builder::new(0);
+        builder.with_transition(HashSet::new(), HashSet::new(), Some(1));
+        builder.with_match();
+        builder.build().debug_assert_valid();
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn test_debug_assert_valid_out_of_range() {
+        let mut builder = Builder::new(0);
+        builder.with_transition(HashSet::new(), HashSet::new(), Some(5));
+        builder.with_match();
+        builder.build().debug_assert_valid();
+    }
+
+    #[test]
+    #[should_panic(expected = "no Match state is reachable")]
+    fn test_debug_assert_valid_unreachable_match() {
+        let mut builder = Builder::new(0);
+        builder.with_transition(HashSet::new(), HashSet::new(), None);
+        builder.with_match();
+        builder.build().debug_assert_valid();
+    }
+
+    #[test]
+    fn test_quantifier_after_group_binds_to_whole_group() {
+        let re = Rejects::new(r"(a|b)+").unwrap();
+        assert_eq!(re.find_end("abba"), 3);
+        assert_eq!(re.find_end("baab"), 3);
+        assert_eq!(re.find_end("abbac"), 3);
+
+        let re = Rejects::new(r"(a|bc)*d").unwrap();
+        assert_eq!(re.find_end("abcbcad"), 6);
+        assert_eq!(re.find_end("d"), 0);
+    }
+
+    #[test]
+    fn test_quantifier_without_group_binds_to_last_alternative() {
+        let re = Rejects::new(r"a|b+").unwrap();
+        assert_eq!(re.find_end("bbb"), 2);
+        assert_eq!(re.find_end("a"), 0);
+        assert_eq!(re.find_end("aa"), 0);
+        assert_eq!(re.find_end("ab"), 0);
+
+        let re = Rejects::new(r"ab|c*").unwrap();
+        assert_eq!(re.find_end("abab"), 1);
+        assert_eq!(re.find_end("ccc"), 2);
+    }
+
+    #[test]
+    fn test_cache_hit() {
+        let mut cache = RejectsCache::new(2);
+        let first = cache.get(r"[a-z]+\d").unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (0, 1));
+        let second = cache.get(r"[a-z]+\d").unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(second.find_end("abc1"), 3);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut cache = RejectsCache::new(2);
+        cache.get(r"a").unwrap();
+        cache.get(r"b").unwrap();
+        cache.get(r"a").unwrap();
+        cache.get(r"c").unwrap();
+        assert_eq!(cache.len(), 2);
+        cache.get(r"a").unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (2, 3));
+        cache.get(r"b").unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (2, 4));
+        assert!(cache.get(r"(a").is_err());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_is_suffix_match() {
+        let re = Rejects::new(r"\d+").unwrap();
+        assert!(re.is_suffix_match("abc123"));
+        assert!(re.is_suffix_match("7"));
+        assert!(!re.is_suffix_match("123abc"));
+        assert!(!re.is_suffix_match(""));
+
+        let re = Rejects::new(r"\d+x").unwrap();
+        assert!(!re.is_suffix_match("abc123"));
+        assert!(re.is_suffix_match("abc123x"));
+
+        let re = Rejects::new(r"(ab)*").unwrap();
+        assert!(re.is_suffix_match(""));
+        assert!(re.is_suffix_match("cccabab"));
+    }
+
+    #[test]
+    fn test_from_dfa() {
+        // a(b|d)*c
+        let dfa = Dfa {
+            start: 0,
+            alphabet: vec!['a', 'b', 'c', 'd'],
+            table: vec![
+                vec![Some(1), None, None, None],
+                vec![None, Some(1), Some(2), Some(1)],
+                vec![None, None, None, None],
+            ],
+            accepting: vec![2].into_iter().collect(),
+        };
+        let re = Rejects::from_dfa(&dfa).unwrap();
+        re.debug_assert_valid();
+        assert_eq!(re.find_end("ac"), 1);
+        assert_eq!(re.find_end("abdbc"), 4);
+        assert_eq!(re.find_end("abbcd"), 3);
+        assert_eq!(re.find_end("ab"), -1);
+        assert_eq!(re.find_end("c"), -1);
+
+        // accepting states with outgoing edges: (ab)*
+        let dfa = Dfa {
+            start: 0,
+            alphabet: vec!['a', 'b'],
+            table: vec![vec![Some(1), None], vec![None, Some(0)]],
+            accepting: vec![0].into_iter().collect(),
+        };
+        let re = Rejects::from_dfa(&dfa).unwrap();
+        re.debug_assert_valid();
+        assert_eq!(re.find_end("abab"), 3);
+        assert_eq!(re.find_end("aba"), 1);
+    }
+
+    #[test]
+    fn test_from_dfa_invalid() {
+        let dfa = Dfa {
+            start: 0,
+            alphabet: vec!['a'],
+            table: vec![vec![Some(1)], vec![Some(2)]],
+            accepting: HashSet::new(),
+        };
+        assert_eq!(Rejects::from_dfa(&dfa).unwrap_err(), 1);
+
+        let dfa = Dfa {
+            start: 0,
+            alphabet: vec!['a', 'b'],
+            table: vec![vec![Some(0)]],
+            accepting: HashSet::new(),
+        };
+        assert_eq!(Rejects::from_dfa(&dfa).unwrap_err(), 0);
+    }
+
+    #[test]
+    fn test_compiled_quantifiers() {
+        let re = Rejects::literal("ab");
+        assert_eq!(re.find_end("abab"), 1);
+
+        // is_suffix_match("") is true exactly when the empty string is matched
+        let re = Rejects::literal("ab").star();
+        re.debug_assert_valid();
+        assert_eq!(re.find_end("ababab"), 5);
+        assert_eq!(re.find_end("ababa"), 3);
+        assert!(re.is_suffix_match(""));
+
+        let re = Rejects::literal("ab").plus();
+        re.debug_assert_valid();
+        assert_eq!(re.find_end("ababab"), 5);
+        assert_eq!(re.find_end("ab"), 1);
+        assert!(!re.is_suffix_match(""));
+
+        let re = Rejects::literal("ab").optional();
+        re.debug_assert_valid();
+        assert_eq!(re.find_end("abab"), 1);
+        assert!(re.is_suffix_match(""));
+
+        let re = Rejects::new(r"a|bc").unwrap().plus().optional();
+        re.debug_assert_valid();
+        assert_eq!(re.find_end("abcbcaa"), 6);
+        assert!(re.is_suffix_match(""));
+    }
+
+    #[test]
+    #[cfg(feature = "ast")]
+    fn test_to_regex_string_round_trip() {
+        let regexes = vec![
+            r"a(b|c)*",
+            r"(a|b+c?|d)",
+            r"(ab)|(cd)*",
+            r"\w+\D?",
+            r"\\\*\(",
+            r"a.c",
+        ];
+        for regex in regexes {
+            let re = Rejects::new(regex).unwrap();
+            let reconstructed = re.to_regex_string().unwrap();
+            assert_eq!(reconstructed, regex);
+            let reparsed = Rejects::new(&reconstructed).unwrap();
+            assert_eq!(
+                parser::parse_ast(regex).unwrap(),
+                parser::parse_ast(&reconstructed).unwrap()
+            );
+            for s in &["", "a", "abcb", "bbc", "cdcd", "ab_1x", "\\*("] {
+                assert_eq!(re.find_end(s), reparsed.find_end(s));
+            }
+        }
+
+        assert_eq!(
+            Rejects::literal("a?").star().to_regex_string().unwrap(),
+            r"(a[?])*"
+        );
+        assert_eq!(Rejects::literal("").to_regex_string(), None);
+        assert_eq!(Builder::new(0).build().to_regex_string(), None);
+    }
+
+    #[test]
+    fn test_parse_ast() {
+        let lit = |c| Box::new(Ast::Literal(c));
+        assert_eq!(
+            parse_ast(r"a(b|c)*").unwrap(),
+            Ast::Concat(
+                lit('a'),
+                Box::new(Ast::Star(Box::new(Ast::Group(Box::new(Ast::Union(
+                    lit('b'),
+                    lit('c')
+                ))))))
+            )
+        );
+        assert_eq!(
+            parse_ast(r"ab+c?").unwrap(),
+            Ast::Concat(
+                lit('a'),
+                Box::new(Ast::Concat(
+                    Box::new(Ast::Plus(lit('b'))),
+                    Box::new(Ast::Optional(lit('c')))
+                ))
+            )
+        );
+        assert_eq!(
+            parse_ast(r"\d").unwrap(),
+            Ast::Class {
+                inclusive: "0123456789".chars().collect(),
+                exclusive: HashSet::new(),
+            }
+        );
+        assert_eq!(
+            parse_ast(r"a)").unwrap_err(),
+            vec![ParseError {
+                index: 1,
+                kind: ParseErrorKind::Unexpected
+            }]
+        );
+
+        // a tree can be changed before it is compiled
+        let ast = match parse_ast(r"a(b|c)*").unwrap() {
+            Ast::Concat(_, r) => Ast::Concat(Box::new(Ast::Literal('x')), r),
+            ast => ast,
+        };
+        let re = Rejects::from_ast(ast);
+        assert_eq!(re.find_end("xbc"), 2);
+        assert_eq!(re.find_end("abc"), -1);
+        assert_eq!(re.to_regex_string().unwrap(), "x(b|c)*");
+    }
+
+    #[test]
+    fn test_factor_common_prefixes() {
+        let cases = vec![
+            (r"(abc|abd)", 8, 6),
+            (r"abc|abd|abe", 12, 8),
+            (r"abc|abd|aef", 12, 9),
+            (r"ab|abc", 7, 6),
+            (r"ab|c|ab", 8, 8),
+            (r"a|ab", 5, 5),
+            (r"(a|b)*", 5, 5),
+        ];
+        for (regex, unoptimized, optimized) in cases {
+            let (start, states) = parse_ast(regex).unwrap().to_nfa();
+            let naive = Rejects::from(start, states);
+            assert_eq!(naive.state_count(), unoptimized, "{}", regex);
+            let re = Rejects::new(regex).unwrap();
+            assert_eq!(re.state_count(), optimized, "{}", regex);
+
+            for s in &["abc", "abd", "abe", "aef", "ab", "a", "c", "abab", "x", ""] {
+                assert_eq!(re.find_end(s), naive.find_end(s), "{} on {}", regex, s);
+                assert_eq!(re.is_suffix_match(s), naive.is_suffix_match(s));
+            }
+        }
+    }
+
+    #[test]
+    fn test_contains() {
+        let re = Rejects::new(r"\d+").unwrap();
+        assert!(re.contains("abc123def"));
+        assert!(re.contains("9"));
+        assert!(!re.contains("abcdef"));
+        assert!(!re.contains(""));
+        assert_eq!(re.find_end("abc123def"), -1);
+
+        let re = Rejects::new(r"ab(cd)*e").unwrap();
+        assert!(re.contains("xxabcdcdeyy"));
+        assert!(re.contains("aabe"));
+        assert!(!re.contains("abcdcd"));
+
+        assert!(Rejects::new(r"a*").unwrap().contains(""));
+    }
+
+    #[test]
+    fn test_contains_literals() {
+        let regexes = vec![
+            r"he|she|his|hers",
+            r"(abc|bcd)|cde",
+            r"(aaab)",
+            r"a|ab|abc",
+            r"\*\(|\\",
+            r"ab|a*",
+            r"bc|abcd",
+            r"é|éa",
+        ];
+        let haystacks = vec![
+            "",
+            "ushers",
+            "ahishers",
+            "h",
+            "hxe",
+            "xxabxbcxcdxx",
+            "zzcde",
+            "aaaab",
+            "aab",
+            "b",
+            "*(",
+            "\\",
+            "xabcde",
+            "éaéé",
+        ];
+        for regex in regexes {
+            let re = Rejects::new(regex).unwrap();
+            let (start, states) = parse_ast(regex).unwrap().to_nfa();
+            let nfa = Rejects::from(start, states);
+            assert!(nfa.literals.is_none());
+            for haystack in haystacks.iter() {
+                assert_eq!(
+                    re.contains(haystack),
+                    nfa.contains(haystack),
+                    "{} in {}",
+                    regex,
+                    haystack
+                );
+                let spans = |re: &Rejects| {
+                    re.find_iter(haystack)
+                        .map(|m| (m.start(), m.end()))
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(spans(&re), spans(&nfa), "{} in {}", regex, haystack);
+            }
+        }
+        let first = Options {
+            match_kind: MatchKind::LeftmostFirst,
+            ..Options::default()
+        };
+        let re = Rejects::with_options(r"bc|abcd|a", &first).unwrap();
+        assert!(re.literals.is_some());
+        let found: Vec<&str> = re.find_iter("xabcdabc").map(|m| m.as_str()).collect();
+        assert_eq!(found, vec!["abcd", "a", "bc"]);
+        assert!(Rejects::new(r"he|she").unwrap().literals.is_some());
+        assert!(Rejects::new(r"ab|a*").unwrap().literals.is_none());
+        assert!(Rejects::new(r"(a|b)c").unwrap().literals.is_none());
+        assert!(Rejects::literal("foo").star().contains("bar"));
+    }
+
+    #[test]
+    fn test_splitn() {
+        let re = Rejects::new(r"=").unwrap();
+        assert_eq!(
+            re.splitn("key=value=with=equals", 2),
+            vec!["key", "value=with=equals"]
+        );
+        assert_eq!(re.splitn("a=b=c", 5), vec!["a", "b", "c"]);
+        assert_eq!(re.splitn("a=b=c", 1), vec!["a=b=c"]);
+        assert_eq!(re.splitn("a=b=c", 0), Vec::<&str>::new());
+        assert_eq!(re.splitn("=a=", 3), vec!["", "a", ""]);
+
+        let re = Rejects::new(r"\s+").unwrap();
+        assert_eq!(re.splitn("é  ü\t\tö x", 3), vec!["é", "ü", "ö x"]);
+
+        let re = Rejects::new(r"b*").unwrap();
+        assert_eq!(re.splitn("abbacbd", 10), vec!["a", "ac", "d"]);
+    }
+
+    #[test]
+    fn test_escaped_brackets() {
+        let re = Rejects::new(r"\[abc\]").unwrap();
+        assert_eq!(re.find_end("[abc]"), 4);
+        assert_eq!(re.find_end("[abc]]"), 4);
+        assert_eq!(re.find_end("abc"), -1);
+
+        let re = Rejects::new(r"\]+\[").unwrap();
+        assert_eq!(re.find_end("]]]["), 3);
+    }
+
+    #[test]
+    fn test_run_to_completion() {
+        let re = Rejects::new(r"a+").unwrap();
+        assert_eq!(re.run_to_completion("aaa"), vec![1, 2, 3]);
+        assert_eq!(re.run_to_completion("aaba"), vec![1, 2]);
+        assert_eq!(re.run_to_completion("baa"), Vec::<usize>::new());
+
+        let re = Rejects::new(r"(ab)*").unwrap();
+        assert_eq!(re.run_to_completion("ababa"), vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn test_ascii_find_end() {
+        let regexes = vec![r"[a-z]+[0-9]?(\d\d\d\d|abcd)*", r"a|b+", r"\w+\s(\w+)?"];
+        let haystacks = [
+            "abcdefg11234abcd1234this is no longer getting matched",
+            "abcdefhijk234",
+            "bbbé",
+            "éa",
+            "ab_9 é",
+            "ab_9 ab",
+            "",
+        ];
+        for regex in regexes {
+            let re = Rejects::new(regex).unwrap();
+            assert!(re.is_ascii(), "{}", regex);
+            for haystack in haystacks.iter() {
+                assert_eq!(
+                    re.find_end(haystack),
+                    re.find_end_iter(haystack.chars()),
+                    "{} on {}",
+                    regex,
+                    haystack
+                );
+            }
+        }
+
+        assert!(!Rejects::new(r"a\W").unwrap().is_ascii());
+        assert!(!Rejects::new(r"é").unwrap().is_ascii());
+    }
+
+    #[test]
+    fn test_is_empty_language() {
+        for regex in &[r"a", r"a*", r"(ab|c)+d?"] {
+            assert!(!Rejects::new(regex).unwrap().is_empty_language());
+        }
+
+        // a Match state exists, but nothing leads to it
+        let mut builder = Builder::new(0);
+        builder.with_split(1, Some(2));
+        builder.with_transition(vec!['a'].into_iter().collect(), HashSet::new(), None);
+        builder.with_nil();
+        builder.with_match();
+        let re = builder.build();
+        assert!(re.is_empty_language());
+        assert_eq!(re.find_end("a"), -1);
+        assert!(!re.contains("a"));
+
+        let mut builder = Builder::new(0);
+        builder.with_nil();
+        assert!(builder.build().is_empty_language());
+    }
+
+    #[test]
+    fn test_walk() {
+        #[derive(Default)]
+        struct Counter {
+            splits: usize,
+            visited: Vec<usize>,
+        }
+
+        impl StateVisitor for Counter {
+            fn visit_split(&mut self, index: usize, _out1: usize, _out2: Option<usize>) {
+                self.splits += 1;
+                self.visited.push(index);
+            }
+
+            fn visit_match(&mut self, index: usize) {
+                self.visited.push(index);
+            }
+
+            fn visit_nil(&mut self, index: usize) {
+                self.visited.push(index);
+            }
+        }
+
+        let re = Rejects::new(r"(a|b)*c?").unwrap();
+        let mut counter = Counter::default();
+        re.walk(true, &mut counter);
+        assert_eq!(counter.splits, 3);
+
+        // the Match state is reachable, the Nil state is not
+        let mut builder = Builder::new(0);
+        builder.with_split(2, None);
+        builder.with_nil();
+        builder.with_split(3, Some(3));
+        builder.with_match();
+        let re = builder.build();
+        let mut counter = Counter::default();
+        re.walk(true, &mut counter);
+        assert_eq!(counter.splits, 2);
+        assert_eq!(counter.visited, vec![0, 2, 3]);
+
+        let mut counter = Counter::default();
+        re.walk(false, &mut counter);
+        assert_eq!(counter.visited, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_dot() {
+        let re = Rejects::new(r"a.c").unwrap();
+        assert_eq!(re.find_end("abc"), 2);
+        assert_eq!(re.find_end("a c"), 2);
+        assert_eq!(re.find_end("aéc"), 2);
+        assert!(!re.contains("a\nc"));
+
+        let mut options = Options {
+            dot_excludes: character_sets::whitespace(),
+            ..Options::default()
+        };
+        let re = Rejects::with_options(r"a.c", &options).unwrap();
+        assert!(re.contains("abc"));
+        assert!(!re.contains("a c"));
+        assert!(!re.contains("a\tc"));
+
+        options.dot_excludes.clear();
+        let re = Rejects::with_options(r".+", &options).unwrap();
+        assert_eq!(re.find_end("a\n c"), 3);
+    }
+
+    #[test]
+    fn test_minimize() {
+        let re = Rejects::new(r"(a|a)").unwrap();
+        let min = re.minimize();
+        min.debug_assert_valid();
+        assert!(min.state_count() < re.state_count());
+        for s in &["", "a", "aa", "b", "ab"] {
+            assert_eq!(min.find_end(s), re.find_end(s));
+            assert_eq!(min.run_to_completion(s), re.run_to_completion(s));
+        }
+
+        let regexes = [
+            r"(a|b)*abb",
+            r"x[^ab]*y",
+            r"(ab|cd)+.?",
+            r"\d+(\w|-)*",
+            r"a*b*",
+        ];
+        for regex in regexes.iter() {
+            let re = Rejects::new(regex).unwrap();
+            let min = re.minimize();
+            min.debug_assert_valid();
+            for s in &[
+                "", "abb", "babb", "abab", "xy", "xcdy", "xay", "abcd", "cdab!", "12a-b_", "aabb",
+                "ba",
+            ] {
+                assert_eq!(
+                    min.run_to_completion(s),
+                    re.run_to_completion(s),
+                    "{} {}",
+                    regex,
+                    s
+                );
+                assert_eq!(min.contains(s), re.contains(s), "{} {}", regex, s);
+            }
+        }
+
+        // strings with an even number of a's, written two ways
+        let left = Rejects::new(r"(b|ab*a)*").unwrap().minimize();
+        let right = Rejects::new(r"b*(ab*ab*)*").unwrap().minimize();
+        assert_eq!(left.state_count(), right.state_count());
+    }
+
+    #[test]
+    fn test_replace_all() {
+        let re = Rejects::new(r"\d+").unwrap();
+        assert_eq!(re.replace_all("a1b22c", "#"), "a#b#c");
+        assert_eq!(re.replace_all("a1b22c", "<$0>"), "a<1>b<22>c");
+        assert_eq!(re.replace_all("a1b22c", "${0}0$$"), "a10$b220$c");
+        assert_eq!(re.replace_all("abc", "#"), "abc");
+        assert_eq!(Rejects::new(r"x*").unwrap().replace_all("axxb", "-"), "a-b");
+    }
+
+    #[test]
+    fn test_try_replace_all_malformed_template() {
+        let re = Rejects::new(r"a").unwrap();
+        let error = |index, kind| Err(ReplaceError { index, kind });
+        assert_eq!(
+            re.try_replace_all("a", "x$"),
+            error(1, ReplaceErrorKind::TrailingDollar)
+        );
+        assert_eq!(
+            re.try_replace_all("a", "ab${0"),
+            error(2, ReplaceErrorKind::UnterminatedBrace)
+        );
+        assert_eq!(
+            re.try_replace_all("a", "$x"),
+            error(0, ReplaceErrorKind::InvalidGroup)
+        );
+        assert_eq!(
+            re.try_replace_all("a", "$0${}"),
+            error(2, ReplaceErrorKind::InvalidGroup)
+        );
+        assert_eq!(
+            re.try_replace_all("a", "é$1"),
+            error(1, ReplaceErrorKind::UnknownGroup)
+        );
+        assert_eq!(re.try_replace_all("bab", "[$0]"), Ok("b[a]b".to_string()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_replace_all_panics_on_malformed_template() {
+        Rejects::new(r"a").unwrap().replace_all("a", "${");
+    }
+
+    #[test]
+    fn test_named_groups() {
+        let named = |name: &str| {
+            Ast::NamedGroup(
+                name.to_string(),
+                Box::new(Ast::Plus(Box::new(Ast::Class {
+                    inclusive: character_sets::digits(),
+                    exclusive: HashSet::new(),
+                }))),
+            )
+        };
+        assert_eq!(parse_ast(r"(?<y>\d+)").unwrap(), named("y"));
+        assert_eq!(parse_ast(r"(?P<y>\d+)").unwrap(), named("y"));
+        assert_eq!(parse_ast(r"(?P<year_1>\d+)").unwrap(), named("year_1"));
+
+        let re = Rejects::new(r"(?P<y>\d+)-(?<m>\d+)").unwrap();
+        assert_eq!(re.run_to_completion("2020-10"), [6, 7]);
+        assert_eq!(re.to_regex_string().unwrap(), r"(?<y>\d+)-(?<m>\d+)");
+
+        for regex in &["(?<>a)", "(?<y a)", "(?y>a)", "(?P=y)", "(?<y-z>a)"] {
+            assert!(Rejects::new(regex).is_err(), "{}", regex);
+        }
+    }
+
+    #[test]
+    fn test_min_max_len() {
+        let lens = |regex| {
+            let re = Rejects::new(regex).unwrap();
+            (re.min_len(), re.max_len())
+        };
+        assert_eq!(lens(r"\d\d\d\d"), (Some(4), Some(4)));
+        assert_eq!(lens(r"a+"), (Some(1), None));
+        assert_eq!(lens(r"a*"), (Some(0), None));
+        assert_eq!(lens(r"ab?c|d"), (Some(1), Some(3)));
+        assert_eq!(lens(r"(ab|c)?(de)?"), (Some(0), Some(4)));
+        assert_eq!(lens(r"x(a|bcd*)"), (Some(2), None));
+        assert_eq!(lens(r"(a?)?b"), (Some(1), Some(2)));
+
+        let empty = Builder::new(0).build();
+        assert_eq!((empty.min_len(), empty.max_len()), (None, Some(0)));
+        // the combinators change what the shortest match is
+        assert_eq!(Rejects::literal("ab").plus().min_len(), Some(2));
+        assert_eq!(Rejects::literal("ab").star().min_len(), Some(0));
+        assert_eq!(Rejects::literal("ab").optional().min_len(), Some(0));
+    }
+
+    #[test]
+    fn test_is_match_os() {
+        let re = Rejects::new(r"src/(\w|/)*\.rs").unwrap();
+        assert!(re.is_match_os(Path::new("src/dfa.rs").as_os_str()));
+        assert!(re.is_match_os(OsStr::new("src/a/b_c.rs")));
+        assert!(!re.is_match_os(OsStr::new("src/dfa.rs.bak")));
+        assert!(!re.is_match_os(OsStr::new("tests/dfa.rs")));
+    }
+
+    #[test]
+    fn test_matcher() {
+        let re = Rejects::new(r"ab+c").unwrap();
+        let mut whole = re.matcher();
+        whole.feed("abbc");
+
+        let mut m = re.matcher();
+        m.feed("ab");
+        assert!(!m.is_match());
+        assert!(!m.active_states().is_empty());
+        m.feed("bc");
+        assert!(m.is_match());
+        assert_eq!(m.active_states(), whole.active_states());
+
+        m.feed("c");
+        assert!(m.is_dead());
+        assert!(!m.is_match());
+    }
+
+    #[test]
+    fn test_starts_with_match() {
+        let re = Rejects::new(r"ab+").unwrap();
+        assert!(re.starts_with_match("abbbx"));
+        assert!(!re.is_match("abbbx"));
+        assert!(re.contains("abbbx"));
+
+        assert!(!re.starts_with_match("xab"));
+        assert!(!re.is_match("xab"));
+        assert!(re.contains("xab"));
+
+        assert!(re.starts_with_match("ab"));
+        assert!(re.is_match("ab"));
+        assert!(!re.starts_with_match("a"));
+
+        let re = Rejects::new(r"a*").unwrap();
+        assert!(re.starts_with_match(""));
+        assert!(re.starts_with_match("b"));
+        assert!(!re.is_match("b"));
+    }
+
+    #[test]
+    fn test_find_iter() {
+        let re = Rejects::new(r"a+|x*").unwrap();
+        let found: Vec<(usize, usize, &str)> = re
+            .find_iter("baaxéa")
+            .map(|m| (m.start(), m.end(), m.as_str()))
+            .collect();
+        assert_eq!(found, [(1, 3, "aa"), (3, 4, "x"), (6, 7, "a")]);
+    }
+
+    #[test]
+    fn test_replace_all_with() {
+        let re = Rejects::new(r"[a-z]+").unwrap();
+        assert_eq!(
+            re.replace_all_with("hi, rust 2018!", |m| m.as_str().to_uppercase()),
+            "HI, RUST 2018!"
+        );
+        assert_eq!(
+            re.replace_all_with("ab1cde", |m| m.as_str().len().to_string()),
+            "213"
+        );
+    }
+
+    #[test]
+    fn test_rejects_set_from_lines() {
+        let set = RejectsSet::from_lines(Cursor::new("foo\n\nba+r\n\\d\\d\n")).unwrap();
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.matches("a foo and a baaar"), [0, 1]);
+        assert_eq!(set.matches("12"), [2]);
+        assert!(!set.is_match("nothing"));
+
+        let errors = RejectsSet::from_lines(Cursor::new("foo\nba(r\nbaz")).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 2);
+
+        let errors = RejectsSet::new(&["a", "*", "b"]).unwrap_err();
+        assert_eq!(errors[0].0, 1);
+    }
+
+    #[test]
+    fn test_rejects_set_longest_match() {
+        let set = RejectsSet::new(&["foo", "fo+bar", "f.*"]).unwrap();
+        assert_eq!(set.longest_match("foobar"), Some((1, 6)));
+        // "foo" and "f.*" both match all of it, the lower index wins
+        assert_eq!(set.longest_match("foo"), Some((0, 3)));
+        assert_eq!(set.longest_match("fob"), Some((2, 3)));
+        assert_eq!(set.longest_match("xfoo"), None);
+
+        let set = RejectsSet::new(&["a", "b*"]).unwrap();
+        assert_eq!(set.longest_match("c"), Some((1, 0)));
+        assert_eq!(RejectsSet::new(&[]).unwrap().longest_match("a"), None);
+    }
+
+    #[test]
+    fn test_rejects_set_compile_trie() {
+        let patterns = [
+            r"http://a\.com",
+            r"http://b\.org",
+            r"http://a\.co",
+            "http://",
+            "ftp",
+        ];
+        let trie = RejectsSet::compile_trie(&patterns).unwrap();
+        let separate = RejectsSet::new(&patterns).unwrap();
+        assert!(trie.state_count() < separate.state_count());
+        for s in &[
+            "http://a.com",
+            "http://a.co",
+            "http://b.org/x",
+            "http://c",
+            "ftp",
+            "ft",
+            "",
+        ] {
+            assert_eq!(trie.longest_match(s), separate.longest_match(s), "{}", s);
+        }
+        assert_eq!(trie.longest_match("http://a.com"), Some((0, 12)));
+        assert_eq!(trie.longest_match("http://x"), Some((3, 7)));
+        assert_eq!(trie.matches("see http://b.org"), vec![1, 3]);
+
+        // the same pattern twice ends at the same place, the lower index wins
+        let trie = RejectsSet::compile_trie(&["a+b", "a+b"]).unwrap();
+        assert_eq!(trie.longest_match("aab"), Some((0, 3)));
+        assert_eq!(
+            RejectsSet::compile_trie(&[]).unwrap().longest_match("a"),
+            None
+        );
+        assert_eq!(RejectsSet::compile_trie(&["a", "("]).unwrap_err()[0].0, 1);
+    }
+
+    #[test]
+    fn test_fullmatch_len() {
+        let re = Rejects::new(r"\d\d-é").unwrap();
+        assert_eq!(re.fullmatch_len("12-é"), Some(5));
+        assert_eq!(re.fullmatch_len("12-éx"), None);
+        assert_eq!(re.fullmatch_len("12-"), None);
+        assert_eq!(Rejects::new(r"a*").unwrap().fullmatch_len(""), Some(0));
+    }
+
+    // Runs the statements ToTokens writes for a state, as the code make_rejects! expands to
+    // would, and returns the state they build.
+    fn state_from_tokens(tokens: proc_macro2::TokenStream) -> State {
+        enum Value {
+            Int(usize),
+            Set(HashSet<char>),
+            Out(Option<usize>),
+            State(State),
+        }
+        fn name(path: &syn::Path) -> String {
+            path.segments.last().unwrap().ident.to_string()
+        }
+        fn eval(expr: &syn::Expr, vars: &mut HashMap<String, Value>) -> Value {
+            let call = match expr {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Int(n),
+                    ..
+                }) => return Value::Int(n.base10_parse().unwrap()),
+                syn::Expr::Path(path) if name(&path.path) == "None" => return Value::Out(None),
+                syn::Expr::Path(path) => return vars.remove(&name(&path.path)).unwrap(),
+                syn::Expr::Call(call) => call,
+                _ => panic!("unexpected expression"),
+            };
+            let func = match &*call.func {
+                syn::Expr::Path(path) => name(&path.path),
+                _ => panic!("expected a function"),
+            };
+            let args: Vec<&syn::Expr> = call.args.iter().collect();
+            let state = match (func.as_str(), args.as_slice()) {
+                ("Some", [n]) => match eval(n, vars) {
+                    Value::Int(n) => return Value::Out(Some(n)),
+                    _ => panic!("expected an integer"),
+                },
+                ("new", []) => return Value::Set(HashSet::new()),
+                ("make_transition", [inclusive, exclusive, out]) => {
+                    match (
+                        eval(inclusive, vars),
+                        eval(exclusive, vars),
+                        eval(out, vars),
+                    ) {
+                        (Value::Set(inclusive), Value::Set(exclusive), Value::Out(out)) => {
+                            State::make_transition(inclusive, exclusive, out)
+                        }
+                        _ => panic!("bad arguments to make_transition"),
+                    }
+                }
+                ("make_split", [out1, out2]) => match (eval(out1, vars), eval(out2, vars)) {
+                    (Value::Int(out1), Value::Out(out2)) => State::make_split(out1, out2),
+                    _ => panic!("bad arguments to make_split"),
+                },
+                ("make_anchor", [syn::Expr::Path(anchor), out]) => {
+                    let anchor = match name(&anchor.path).as_str() {
+                        "Start" => Anchor::Start,
+                        "End" => Anchor::End,
+                        "LineStart" => Anchor::LineStart,
+                        "LineEnd" => Anchor::LineEnd,
+                        "EndBeforeNewline" => Anchor::EndBeforeNewline,
+                        anchor => panic!("unknown anchor {}", anchor),
+                    };
+                    match eval(out, vars) {
+                        Value::Out(out) => State::make_anchor(anchor, out),
+                        _ => panic!("bad arguments to make_anchor"),
+                    }
+                }
+                ("make_match", []) => State::make_match(),
+                ("make_nil", []) => State::make_nil(),
+                (func, _) => panic!("unknown call to {}", func),
+            };
+            Value::State(state)
+        }
+
+        let block: syn::Block = syn::parse2(tokens).unwrap();
+        let mut vars = HashMap::new();
+        for stmt in &block.stmts {
+            match stmt {
+                syn::Stmt::Local(local) => {
+                    let pat = match &local.pat {
+                        syn::Pat::Type(typed) => &*typed.pat,
+                        pat => pat,
+                    };
+                    let var = match pat {
+                        syn::Pat::Ident(ident) => ident.ident.to_string(),
+                        _ => panic!("expected a variable"),
+                    };
+                    let value = eval(&local.init.as_ref().unwrap().1, &mut vars);
+                    vars.insert(var, value);
+                }
+                syn::Stmt::Semi(syn::Expr::MethodCall(call), _) => {
+                    let set = match &*call.receiver {
+                        syn::Expr::Path(path) => vars.get_mut(&name(&path.path)),
+                        _ => None,
+                    };
+                    match (set, call.args.first()) {
+                        (
+                            Some(Value::Set(set)),
+                            Some(syn::Expr::Lit(syn::ExprLit {
+                                lit: syn::Lit::Char(c),
+                                ..
+                            })),
+                        ) => {
+                            set.insert(c.value());
+                        }
+                        _ => panic!("expected an insert into a set"),
+                    }
+                }
+                syn::Stmt::Expr(expr) => match eval(expr, &mut vars) {
+                    Value::State(state) => return state,
+                    _ => panic!("expected a state"),
+                },
+                _ => panic!("unexpected statement"),
+            }
+        }
+        panic!("no state was built")
+    }
+
+    #[test]
+    fn test_state_to_tokens() {
+        // ToTokens is what make_rejects! expands to, so the states built from its tokens have to
+        // match the same text as the states they were written out from
+        let multiline = Options {
+            multiline: true,
+            ..Options::default()
+        };
+        let machines = [
+            Rejects::new(r"^a[^b]c|d$").unwrap(),
+            Rejects::new(r"(ab|cd)*e?").unwrap(),
+            Rejects::with_options(r"^x+$|y", &multiline).unwrap(),
+            Rejects::new(r"d\Z|x+\z").unwrap(),
+        ];
+        let inputs = [
+            "", "aac", "abc", "a\nc", "xd", "d\n", "abab", "cde", "x", "y\nxx\nz", "xx\n",
+        ];
+        for re in &machines {
+            let states = re
+                .statelist
+                .iter()
+                .map(|state| state_from_tokens(quote::quote!(#state)))
+                .collect();
+            let rebuilt = Rejects::from(re.start, states);
+            for s in &inputs {
+                let spans = |re: &Rejects| -> Vec<(usize, usize)> {
+                    re.find_iter(s).map(|m| (m.start(), m.end())).collect()
+                };
+                assert_eq!(spans(&rebuilt), spans(re), "{:?}", s);
+                assert_eq!(rebuilt.is_match(s), re.is_match(s), "{:?}", s);
+            }
+        }
+        // compiling never leaves a Nil state behind
+        let nil = State::make_nil();
+        assert!(matches!(state_from_tokens(quote::quote!(#nil)), State::Nil));
+    }
+
+    #[test]
+    fn test_with_prefilter() {
+        // words only start after a space (or at the beginning)
+        let after_space = |s: &str, from: usize| {
+            if from == 0 {
+                Some(0)
+            } else {
+                s[from..].find(' ').map(|i| from + i + 1)
+            }
+        };
+        let re = Rejects::new(r"ab+").unwrap().with_prefilter(after_space);
+        let found: Vec<&str> = re.find_iter("ab xabb abbb").map(|m| m.as_str()).collect();
+        assert_eq!(found, ["ab", "abbb"]);
+        assert!(re.contains("x abb"));
+
+        // falls back to trying every position
+        let re = Rejects::new(r"ab+").unwrap().with_prefilter(|_, _| None);
+        assert!(re.contains("xabb"));
+        assert_eq!(re.splitn("1ab2abb3", 3), ["1", "2", "3"]);
+    }
+
+    #[test]
+    #[cfg(feature = "unicode-names")]
+    fn test_character_names() {
+        let re = Rejects::new(r"\N{BULLET} \N{GREEK SMALL LETTER ALPHA}+").unwrap();
+        assert!(re.is_match("• αα"));
+        assert!(!re.is_match("* a"));
+    }
+
+    #[test]
+    fn test_unknown_character_name() {
+        assert_eq!(
+            Rejects::new(r"a\N{NOT A CHARACTER NAME}").unwrap_err()[0].kind,
+            ParseErrorKind::UnknownCharacterName
+        );
+        assert!(Rejects::new(r"\N{BULLET").is_err());
+        assert!(Rejects::new(r"\NBULLET").is_err());
+    }
+
+    #[test]
+    fn test_count_capped() {
+        let re = Rejects::new(r"a").unwrap();
+        let s = "a".repeat(100_000);
+        assert_eq!(re.count_capped(&s, 10), 10);
+        assert_eq!(re.count_capped("banana", 10), 3);
+        assert_eq!(re.count_capped("banana", 0), 0);
+    }
+
+    #[test]
+    fn test_matches_anywhere_count() {
+        let re = Rejects::new(r"aa").unwrap();
+        assert_eq!(re.matches_anywhere_count("aaaa", false), 2);
+        assert_eq!(re.matches_anywhere_count("aaaa", true), 3);
+        assert_eq!(re.matches_anywhere_count("abab", true), 0);
+
+        let re = Rejects::new(r"b*").unwrap();
+        assert_eq!(re.matches_anywhere_count("abba", false), 1);
+        assert_eq!(re.matches_anywhere_count("abba", true), 2);
+    }
+
+    #[test]
+    fn test_match_kind() {
+        let first = Options {
+            match_kind: MatchKind::LeftmostFirst,
+            ..Options::default()
+        };
+        let find = |re: &Rejects, s| re.find_iter(s).next().map(|m| m.as_str().to_string());
+
+        let longest = Rejects::new(r"a|ab").unwrap();
+        assert_eq!(find(&longest, "ab").as_deref(), Some("ab"));
+        let re = Rejects::with_options(r"a|ab", &first).unwrap();
+        assert_eq!(find(&re, "ab").as_deref(), Some("a"));
+        let re = Rejects::with_options(r"ab|a", &first).unwrap();
+        assert_eq!(find(&re, "xab").as_deref(), Some("ab"));
+
+        // quantifiers are greedy but stop where an earlier alternative already matched
+        let re = Rejects::with_options(r"(a|ab)(c|bcd)", &first).unwrap();
+        assert_eq!(find(&re, "abcd").as_deref(), Some("abcd"));
+        let re = Rejects::with_options(r"a*(b|bc)", &first).unwrap();
+        assert_eq!(find(&re, "aabc").as_deref(), Some("aab"));
+        let re = Rejects::with_options(r"x(a*)*y?", &first).unwrap();
+        assert_eq!(find(&re, "xaay").as_deref(), Some("xaay"));
+        assert_eq!(
+            Rejects::with_options(r"a|b", &first)
+                .unwrap()
+                .replace_all("cab", "-"),
+            "c--"
+        );
+    }
+
+    #[test]
+    fn test_simplify_nested_quantifiers() {
+        let pairs = [
+            (r"(a*)?", r"a*"),
+            (r"(a?)?", r"a?"),
+            (r"(a+)*", r"a*"),
+            (r"((a?))+b", r"a*b"),
+            (r"(a+)+", r"a+"),
+            (r"(a*b*)?c", r"a*b*c"),
+        ];
+        for (nested, simple) in pairs.iter() {
+            let nested_re = Rejects::new(nested).unwrap();
+            let simple_re = Rejects::new(simple).unwrap();
+            assert_eq!(
+                nested_re.state_count(),
+                simple_re.state_count(),
+                "{}",
+                nested
+            );
+            for s in &["", "a", "aa", "b", "ab", "aab", "abc", "c", "ba"] {
+                assert_eq!(
+                    nested_re.run_to_completion(s),
+                    simple_re.run_to_completion(s),
+                    "{} {}",
+                    nested,
+                    s
+                );
+            }
+            let (_, unoptimized) = parse_ast(nested).unwrap().to_nfa();
+            assert!(nested_re.state_count() <= unoptimized.len(), "{}", nested);
+        }
+        let (_, unoptimized) = parse_ast(r"(a+)*").unwrap().to_nfa();
+        assert!(Rejects::new(r"(a+)*").unwrap().state_count() < unoptimized.len());
+    }
+
+    #[test]
+    fn test_resume() {
+        let re = Rejects::new(r"foo\d+bar").unwrap();
+        let mut state = re.match_state();
+        assert!(!state.is_match());
+        re.resume(&mut state, "foo12");
+        assert!(!state.is_match());
+        let saved = state.clone();
+        re.resume(&mut state, "3ba");
+        re.resume(&mut state, "r");
+        assert!(state.is_match());
+        re.resume(&mut state, "x");
+        assert!(state.is_dead());
+
+        let mut state = saved;
+        re.resume(&mut state, "bar");
+        assert!(state.is_match());
+
+        let mut m = re.matcher();
+        m.feed("foo1");
+        let mut state = m.into_state();
+        re.resume(&mut state, "bar");
+        assert!(state.is_match());
+        assert!(Rejects::new(r"a*").unwrap().match_state().is_match());
+    }
+
+    #[test]
+    fn test_single_character_patterns() {
+        let re = Rejects::new(r"a").unwrap();
+        assert_eq!(re.state_count(), 2);
+        assert_eq!(re.find_end("ab"), 0);
+        assert!(!re.contains("bc"));
+
+        let re = Rejects::new(r"\d").unwrap();
+        assert_eq!(re.state_count(), 2);
+        assert!(re.is_match("7"));
+        assert!(!re.is_match("x"));
+        assert_eq!(Rejects::new(r".").unwrap().state_count(), 2);
+        assert!(!Rejects::new(r".").unwrap().is_match("\n"));
+
+        // these still go through the parser
+        for regex in &["*", "(", "\\", "[", "|"] {
+            assert!(Rejects::new(regex).is_err(), "{}", regex);
+        }
+        assert!(Rejects::new(r"\*").unwrap().is_match("*"));
+    }
+
+    #[test]
+    fn test_replace_stream() {
+        let replace = |regex, s: &str, capacity| {
+            let re = Rejects::new(regex).unwrap();
+            let mut out = Vec::new();
+            let r = BufReader::with_capacity(capacity, s.as_bytes());
+            re.replace_stream(r, &mut out, "[$0]").unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert_eq!(
+                out,
+                re.replace_all(s, "[$0]"),
+                "{} {} {}",
+                regex,
+                s,
+                capacity
+            );
+            out
+        };
+        // the matches span the 3 byte buffers
+        assert_eq!(
+            replace(r"foo\d?", "xx foo1 fo o foofoo", 3),
+            "xx [foo1] fo o [foo][foo]"
+        );
+        for capacity in 1..8 {
+            replace(r"é+|ab", "aébéécabé", capacity);
+            replace(r"a+", "baaaaacaa", capacity);
+            replace(r"b*", "abbab", capacity);
+        }
+
+        let re = Rejects::new(r"a").unwrap();
+        let mut out = Vec::new();
+        let error = re
+            .replace_stream(Cursor::new("a"), &mut out, "$")
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        let error = re
+            .replace_stream(Cursor::new(&[b'a', 0xff][..]), &mut out, "b")
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_case_insensitive_flag() {
+        let full = |regex, s| Rejects::new(regex).unwrap().is_match(s);
+        assert!(full(r"(?i)abc", "AbC"));
+        assert!(full(r"(?i)abc(?-i)def", "ABCdef"));
+        assert!(!full(r"(?i)abc(?-i)def", "ABCDEF"));
+        assert!(!full(r"abc", "ABC"));
+
+        // flags only last until the end of their group
+        assert!(full(r"(a(?i)b)c", "aBc"));
+        assert!(!full(r"(a(?i)b)c", "aBC"));
+        assert!(!full(r"(a(?i)b)c", "ABc"));
+        assert!(full(r"a(?i:b|d)c", "aDc"));
+        assert!(!full(r"a(?i:b)c", "aBC"));
+        assert!(full(r"(?i)x|y", "Y"));
+        assert!(full(r"a(?i)", "a"));
+
+        assert!(full(r"(?i)[a-d]+\d", "aBc1"));
+        assert!(!full(r"(?i)[^a]", "A"));
+        assert!(full(r"(?i)é", "É"));
+
+        for regex in &["(?i", "(?x)a", "(?-)a", "(?i-)a", "(?--i)a", "(?i)"] {
+            assert!(Rejects::new(regex).is_err(), "{}", regex);
+        }
+        // the character after a bad flag group is still parsed
+        let errors = Rejects::new("(?i-)(a").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, ParseErrorKind::Unexpected);
+        assert_eq!(errors[0].index, 4);
+    }
+
+    // what Rejects::to_static_tokens produces for "ab*|c", written out by hand
+    const STATIC_AB_STAR_OR_C: StaticRejects = StaticRejects::new(
+        4,
+        &[
+            StaticState::Transition {
+                inclusive: &['a'],
+                exclusive: &[],
+                out: Some(2),
+            },
+            StaticState::Transition {
+                inclusive: &['b'],
+                exclusive: &[],
+                out: Some(2),
+            },
+            StaticState::Split {
+                out1: 1,
+                out2: Some(6),
+            },
+            StaticState::Transition {
+                inclusive: &['c'],
+                exclusive: &[],
+                out: Some(6),
+            },
+            StaticState::Split {
+                out1: 0,
+                out2: Some(3),
+            },
+            StaticState::Nil,
+            StaticState::Match,
+        ],
+    );
+
+    #[test]
+    fn test_static_rejects() {
+        static RE: StaticRejects = STATIC_AB_STAR_OR_C;
+        let runtime = Rejects::new("ab*|c").unwrap();
+        for s in &["", "a", "ab", "abbb", "abc", "c", "cc", "b", "ba"] {
+            assert_eq!(
+                RE.run_to_completion(s),
+                runtime.run_to_completion(s),
+                "{}",
+                s
+            );
+            assert_eq!(RE.is_full_match(s), runtime.is_match(s), "{}", s);
+            assert_eq!(
+                RE.starts_with_match(s),
+                runtime.starts_with_match(s),
+                "{}",
+                s
+            );
+        }
+        assert_eq!(RE.state_count(), 7);
+
+        // sets are searched by binary search
+        const CLASS: StaticRejects = StaticRejects::new(
+            0,
+            &[
+                StaticState::Split {
+                    out1: 1,
+                    out2: Some(2),
+                },
+                StaticState::Transition {
+                    inclusive: &[],
+                    exclusive: &['0', '5', 'a', 'é'],
+                    out: Some(0),
+                },
+                StaticState::Match,
+            ],
+        );
+        assert!(CLASS.is_full_match("xyz"));
+        assert!(!CLASS.is_full_match("x5"));
+        assert!(!CLASS.is_full_match("é"));
+        assert_eq!(CLASS.run_to_completion("xy5z"), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_to_static_tokens() {
+        let tokens = Rejects::new("[ba]c*")
+            .unwrap()
+            .to_static_tokens()
+            .to_string();
+        assert!(tokens.contains("StaticRejects :: new"));
+        assert!(tokens.contains("'a' , 'b'"));
+        assert!(tokens.contains("StaticState :: Split"));
+        assert!(tokens.contains("StaticState :: Match"));
+    }
+
+    #[test]
+    fn test_posix_classes() {
+        let full = |regex, s| Rejects::new(regex).unwrap().is_match(s);
+        assert!(full(r"[[:digit:]]", "5"));
+        assert!(!full(r"[[:digit:]]", "a"));
+        assert!(full(r"[[:^digit:]]", "a"));
+        assert!(!full(r"[[:^digit:]]", "5"));
+        assert!(full(r"[[:^digit:]]", "é"));
+        assert!(full(r"[[:alpha:]_]+", "snake_Case"));
+        assert!(!full(r"[[:alpha:]_]+", "snake-case"));
+        assert!(full(r"[[:upper:][:digit:]]+", "A1B2"));
+        assert!(!full(r"[[:upper:][:digit:]]+", "a1"));
+        assert!(full(r"[[:xdigit:]]+", "DeadBeef09"));
+        assert!(full(r"[[:space:]]", "\n"));
+        assert!(!full(r"[[:blank:]]", "\n"));
+        assert!(full(r"[[:punct:]]", "!"));
+        assert!(full(r"[[:^alpha:]]", "1"));
+        assert!(!full(r"[[:^alpha:]]", "q"));
+
+        for regex in &["[[:alpha]]", "[[:nope:]]", "[[:^:]]", "[[:alpha:]"] {
+            assert!(Rejects::new(regex).is_err(), "{}", regex);
+        }
+    }
+
+    #[test]
+    fn test_match_starts() {
+        let starts = |regex, s| Rejects::new(regex).unwrap().match_starts(s);
+        assert_eq!(starts(r"\d+", "a1b2"), vec![false, true, false, true]);
+        assert_eq!(starts(r"\d+", "a12"), vec![false, true, true]);
+        assert_eq!(starts(r"ab", "aabé"), vec![false, true, false, false]);
+        assert_eq!(starts(r"x*", "ab"), vec![true, true]);
+        assert!(starts(r"a", "").is_empty());
+    }
+
+    #[test]
+    fn test_rejects_builder() {
+        let re = Rejects::builder()
+            .case_insensitive(true)
+            .dotall(true)
+            .compile("a.c")
+            .unwrap();
+        assert!(re.is_match("A\nC"));
+        assert!(re.is_match("abC"));
+        let re = Rejects::builder().dotall(false).compile("a.c").unwrap();
+        assert!(!re.is_match("a\nc"));
+        assert!(!re.is_match("abC"));
+        assert!(Rejects::builder()
+            .case_insensitive(true)
+            .compile("x")
+            .unwrap()
+            .is_match("X"));
+
+        let re = Rejects::builder().anchored(true).compile("ab").unwrap();
+        assert!(re.contains("abc"));
+        assert!(!re.contains("cab"));
+        let found: Vec<&str> = re.find_iter("ababcab").map(|m| m.as_str()).collect();
+        assert_eq!(found, vec!["ab", "ab"]);
+
+        let builder = Rejects::builder()
+            .max_states(4)
+            .match_kind(MatchKind::LeftmostFirst);
+        assert_eq!(builder.options().match_kind, MatchKind::LeftmostFirst);
+        assert!(builder.compile("ab").is_ok());
+        let errors = builder.compile("abcdefgh").unwrap_err();
+        assert_eq!(errors[0].kind, ParseErrorKind::TooManyStates);
+    }
+
+    #[test]
+    fn test_multiline() {
+        let re = Rejects::builder().multiline(true).compile(r"^b+$").unwrap();
+        assert!(re.contains("a\nbb\nc"));
+        assert!(re.contains("bb"));
+        assert!(!re.contains("a\nbbc"));
+        assert!(!re.contains("abb\nc"));
+        let found: Vec<&str> = re.find_iter("b\nab\nbb").map(|m| m.as_str()).collect();
+        assert_eq!(found, vec!["b", "bb"]);
+        assert_eq!(re.match_starts("b\nb"), vec![true, false, true]);
+        assert_eq!(re.all_longest_matches("b\nb"), vec![(0, 1), (2, 3)]);
+        assert!(!Rejects::new(r"^b+$").unwrap().contains("a\nbb\nc"));
+
+        // the '\n' is still a character a match can go over
+        let re = Rejects::builder()
+            .multiline(true)
+            .compile(r"a$\n^b")
+            .unwrap();
+        assert!(re.is_match("a\nb"));
+        assert_eq!(re.find_end("a\nbc"), 2);
+        assert_eq!(re.run_to_completion("a\nb"), vec![3]);
+        assert!(re.is_suffix_match("xa\nb"));
+        assert!(!Rejects::new(r"a$\n^b").unwrap().is_match("a\nb"));
+        // a match which ends at a '$' is only found once the '\n' after it is seen
+        let re = Rejects::builder().multiline(true).compile(r"a+$").unwrap();
+        assert_eq!(re.find_end("aa\nb"), 1);
+        assert_eq!(re.run_to_completion("aa\n"), vec![2]);
+        assert!(re.starts_with_match("a\n"));
+        assert!(!re.starts_with_match("ab"));
+        let mut matcher = re.matcher();
+        matcher.feed("a");
+        assert!(matcher.is_match());
+        matcher.feed("\n");
+        assert!(!matcher.is_match());
+        assert!(matcher.is_dead());
+        let re = Rejects::builder()
+            .multiline(true)
+            .match_kind(MatchKind::LeftmostFirst)
+            .compile(r"(a|ab)$")
+            .unwrap();
+        assert_eq!(re.find_iter("xab\n").next().map(|m| m.as_str()), Some("ab"));
+        let re = Rejects::builder().multiline(true).compile(r"^").unwrap();
+        assert!(re.is_match(""));
+        assert_eq!(re.to_regex_string(), Some("^".to_string()));
+    }
+
+    #[test]
+    fn test_accepts_through_trailing_splits() {
+        // the machine ends on a Split which only reaches Match by epsilon in each of these
+        let cases = [
+            (r"a+", "a", vec![1]),
+            (r"a+", "aa", vec![1, 2]),
+            (r"a*", "", vec![0]),
+            (r"a*", "aa", vec![0, 1, 2]),
+            (r"a?", "", vec![0]),
+            (r"a?", "a", vec![0, 1]),
+            (r"(ab)+", "abab", vec![2, 4]),
+            (r"(a|b)*", "ab", vec![0, 1, 2]),
+            (r"ab*", "a", vec![1]),
+            (r"a*b*", "a", vec![0, 1]),
+            (r"(a+)?", "", vec![0]),
+            (r"(a|bc?)+", "ab", vec![1, 2]),
+        ];
+        for (regex, s, expected) in cases.iter() {
+            let re = Rejects::new(regex).unwrap();
+            assert_eq!(&re.run_to_completion(s), expected, "{} {}", regex, s);
+            assert!(re.is_match(s), "{} {}", regex, s);
+            assert_eq!(re.find_end(s), s.len() as isize - 1, "{} {}", regex, s);
+            assert_eq!(
+                &re.minimize().run_to_completion(s),
+                expected,
+                "{} {}",
+                regex,
+                s
+            );
+        }
+
+        // the combinators applied to a compiled machine
+        assert_eq!(
+            Rejects::literal("a").plus().run_to_completion("aa"),
+            vec![1, 2]
+        );
+        assert_eq!(Rejects::literal("a").star().run_to_completion(""), vec![0]);
+        assert_eq!(
+            Rejects::literal("ab").optional().run_to_completion("ab"),
+            vec![0, 2]
+        );
+        assert_eq!(
+            Rejects::literal("a").plus().star().run_to_completion("aa"),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn test_grapheme_spans() {
+        // "é" written as an 'e' and a combining acute accent is 2 characters but 1 cluster
+        let s = "e\u{301}xe\u{301}y";
+        let spans = |regex| Rejects::new(regex).unwrap().grapheme_spans(s);
+        assert_eq!(spans("x"), vec![(1, 2)]);
+        assert_eq!(spans("x.+"), vec![(1, 4)]);
+        assert_eq!(spans("y"), vec![(3, 4)]);
+        assert_eq!(spans("e"), vec![(0, 1), (2, 3)]);
+        assert_eq!(spans("\u{301}x"), vec![(0, 2)]);
+        assert!(spans("z").is_empty());
+    }
+
+    #[test]
+    fn test_is_match() {
+        let re = Rejects::new(r"\d+-").unwrap();
+        assert!(re.is_match("12-"));
+        assert!(!re.is_match("12-34"));
+        assert!(!re.is_match("12"));
+        assert!(!re.is_match("a1-"));
+        assert!(Rejects::new("a*").unwrap().is_match(""));
+        assert!(!Rejects::new("a").unwrap().is_match(""));
+        assert!(!Rejects::new("a*").unwrap().is_match("ab"));
+        // is_full_match is the old name of is_match
+        #[allow(deprecated)]
+        {
+            assert!(re.is_full_match("12-"));
+            assert!(!re.is_full_match("12-34"));
+        }
+
+        // reading stops at the 'x' since no state is left, a match has to read all of `long`
+        let long = "2".repeat(100_000);
+        let re = Rejects::new(r"\d*").unwrap();
+        assert!(!re.is_match(&format!("1x{}", long)));
+        assert!(re.is_match(&long));
+        let mut read = 0;
+        let chars = "1x".chars().chain(long.chars()).inspect(|_| read += 1);
+        assert!(!re.is_full_match_iter(chars));
+        assert_eq!(read, 2);
+        let mut read = 0;
+        assert!(re.is_full_match_iter(long.chars().inspect(|_| read += 1)));
+        assert_eq!(read, long.len());
+    }
+
+    #[test]
+    fn test_replace_no_expand() {
+        let re = Rejects::new(r"\d+").unwrap();
+        assert_eq!(re.replace_all("a1b22c", NoExpand("$1")), "a$1b$1c");
+        assert_eq!(re.replace_all("a1", NoExpand("$0$$")), "a$0$$");
+        assert_eq!(
+            re.try_replace_all("a1", NoExpand("$")),
+            Ok("a$".to_string())
+        );
+        // the same as escaping each '$' in a template
+        assert_eq!(re.replace_all("a1", "$$1"), "a$1");
+        let template = String::from("<$0>");
+        assert_eq!(re.replace_all("a1", &template), "a<1>");
+
+        let mut out = Vec::new();
+        re.replace_stream(Cursor::new("x12y"), &mut out, NoExpand("${0}"))
+            .unwrap();
+        assert_eq!(out, b"x${0}y");
+    }
+
+    #[test]
+    fn test_nfa_to_dfa_states_estimate() {
+        let estimate = |regex, limit| {
+            Rejects::new(regex)
+                .unwrap()
+                .nfa_to_dfa_states_estimate(limit)
+        };
+        assert_eq!(estimate("ab", 10), Ok(3));
+        assert_eq!(estimate("ab", 3), Ok(3));
+        assert_eq!(estimate("ab", 2), Err(TooLarge { limit: 2 }));
+        assert_eq!(estimate("a*", 10), Ok(1));
+
+        // the DFA has to remember the last n characters, so it needs 2^n states
+        assert_eq!(estimate("(a|b)*a(a|b)(a|b)", 100), Ok(8));
+        let blowup = format!("(a|b)*a{}", "(a|b)".repeat(15));
+        assert_eq!(estimate(&blowup, 1000), Err(TooLarge { limit: 1000 }));
+    }
+
+    #[test]
+    fn test_class_subtraction() {
+        let full = |regex, s| Rejects::new(regex).unwrap().is_match(s);
+        assert!(full(r"[a-z-[aeiou]]", "b"));
+        assert!(!full(r"[a-z-[aeiou]]", "a"));
+        assert!(!full(r"[a-z-[aeiou]]", "e"));
+        assert!(full(r"[a-z-[aeiou]]+", "rhythm"));
+        assert!(full(r"[\w-[\d]]+", "a_B"));
+        assert!(!full(r"[\w-[\d]]", "5"));
+
+        // the base is negated before subtracting
+        assert!(full(r"[^ab-[x]]", "d"));
+        assert!(!full(r"[^ab-[x]]", "x"));
+        assert!(!full(r"[^ab-[x]]", "a"));
+        assert!(full(r"[abc-[^b]]", "b"));
+        assert!(!full(r"[abc-[^b]]", "c"));
+        assert!(full(r"[a-y-[a-f-[c]]]", "c"));
+        assert!(!full(r"[a-y-[a-f-[c]]]", "d"));
+        assert!(full(r"[a-y-[a-f-[c]]]", "g"));
+
+        let errors = Rejects::new(r"[ab-[ab]]").unwrap_err();
+        assert_eq!(errors[0].kind, ParseErrorKind::EmptyClass);
+        assert!(Rejects::new(r"[a-[\s\S]]").is_err());
+        assert!(Rejects::new(r"[a-e-[b]x]").is_err());
+        assert!(Rejects::new(r"[a-e-[b]").is_err());
+
+        // without a base it's the characters
+        assert!(full(r"[-[a]", "-"));
+        assert!(full(r"[-[a]", "["));
+        assert!(full(r"[-[a]+", "a-["));
+        let class = parse_ast(r"[-[a]").unwrap();
+        assert_eq!(class.to_string(), r"[-[a]");
+        assert_eq!(parse_ast(&class.to_string()).unwrap(), class);
+    }
+
+    #[test]
+    fn test_could_match_len() {
+        let lens = |regex| {
+            let re = Rejects::new(regex).unwrap();
+            (0..8)
+                .filter(|&n| re.could_match_len(n))
+                .collect::<Vec<usize>>()
+        };
+        assert_eq!(lens(r"\d\d\d\d"), vec![4]);
+        assert_eq!(lens(r"a+"), vec![1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(lens(r"a*"), vec![0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(lens(r"ab?c?"), vec![1, 2, 3]);
+        assert_eq!(lens(r"ab|cdef"), vec![2, 3, 4]);
+        assert_eq!(lens(r"x(ab)+"), vec![3, 4, 5, 6, 7]);
+        assert!(!Rejects::new("ab").unwrap().could_match_len(usize::MAX));
+
+        let mut builder = Builder::new(0);
+        builder.with_nil();
+        assert!(!builder.build().could_match_len(0));
+    }
+
+    #[test]
+    fn test_compile_cached() {
+        let pat = r"compile_cached(\d+)?";
+        let threads: Vec<_> = (0..8)
+            .map(|_| std::thread::spawn(move || crate::compile_cached(pat).unwrap()))
+            .collect();
+        let compiled: Vec<Arc<Rejects>> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        for rejects in compiled.iter() {
+            assert!(Arc::ptr_eq(rejects, &compiled[0]));
+        }
+        assert!(compiled[0].is_match("compile_cached12"));
+        assert!(Arc::ptr_eq(
+            &crate::compile_cached(pat).unwrap(),
+            &compiled[0]
+        ));
+        assert!(crate::compile_cached("compile_cached(").is_err());
+    }
+
+    #[test]
+    fn test_find_end_at_end_of_input() {
+        let re = Rejects::new("a.").unwrap();
+        assert_eq!(re.find_end("a"), -1);
+        assert_eq!(re.find_end("ab"), 1);
+        assert_eq!(re.find_end("abc"), 1);
+        // '.' doesn't match a newline, so the states die on it without anything matching
+        assert_eq!(re.find_end("a\n"), -1);
+        assert_eq!(re.find_end(""), -1);
+
+        // the longest match is kept when the states die after it
+        let re = Rejects::new("ab(cd)*").unwrap();
+        assert_eq!(re.find_end("abcdc"), 3);
+        assert_eq!(re.find_end("abcdcx"), 3);
+        assert_eq!(re.find_end("abc"), 1);
+        assert_eq!(re.find_end("ax"), -1);
+        assert_eq!(Rejects::new("a*").unwrap().find_end("b"), -1);
+    }
+
+    #[test]
+    fn test_find_iter_skips_short_tails() {
+        // the longest non-empty match at each position, moving past each one found
+        let naive = |re: &Rejects, s: &str| {
+            let mut found = Vec::new();
+            let mut pos = 0;
+            while let Some(c) = s[pos..].chars().next() {
+                match re.run_to_completion(&s[pos..]).last() {
+                    Some(&n) if n > 0 => {
+                        let end = pos + s[pos..].chars().take(n).map(char::len_utf8).sum::<usize>();
+                        found.push((pos, end));
+                        pos = end;
+                    }
+                    _ => pos += c.len_utf8(),
+                }
+            }
+            found
+        };
+        let inputs = [
+            "", "a", "abcabc", "xxabcé", "abcab", "ééabc", "aaaa", "ab\ncab",
+        ];
+        for regex in &["abc", "a(bc)?", "a*", "[^c]b", ".b+c", "é+|abc"] {
+            let re = Rejects::new(regex).unwrap();
+            for s in inputs.iter() {
+                let found: Vec<(usize, usize)> =
+                    re.find_iter(s).map(|m| (m.start(), m.end())).collect();
+                assert_eq!(found, naive(&re, s), "{} {:?}", regex, s);
+                let (_, upper) = re.find_iter(s).size_hint();
+                assert!(found.len() <= upper.unwrap(), "{} {:?}", regex, s);
+            }
+        }
+
+        let re = Rejects::new("abc").unwrap();
+        assert_eq!(re.find_iter("abcab").size_hint(), (0, Some(1)));
+        assert_eq!(re.find_iter("ab").size_hint(), (0, Some(0)));
+        let mut iter = re.find_iter("abcabc");
+        iter.next();
+        assert_eq!(iter.size_hint(), (0, Some(1)));
+        assert_eq!(re.find_iter("abcabcab").count(), 2);
+    }
+
+    #[test]
+    fn test_emit_rust() {
+        let source = Rejects::new("a(b|c)*").unwrap().emit_rust("vendored");
+        let file = syn::parse_file(&source).unwrap();
+        assert_eq!(file.items.len(), 1);
+        match &file.items[0] {
+            syn::Item::Fn(f) => {
+                assert_eq!(f.sig.ident, "vendored");
+                assert!(f.sig.inputs.is_empty());
+            }
+            _ => panic!("expected a function"),
+        }
+        // how the printed tokens are spaced out depends on the proc-macro2 version
+        let compact = |source: &str| source.split_whitespace().collect::<String>();
+        assert!(compact(&source).contains("builder.build()"));
+        assert!(!compact(&source).contains("builder.anchored"));
+
+        // the options a machine keeps outside of its states are set again
+        let options = Options {
+            match_kind: MatchKind::LeftmostFirst,
+            anchored: true,
+            ..Options::default()
+        };
+        let source = Rejects::with_options("a|ab", &options)
+            .unwrap()
+            .emit_rust("vendored");
+        assert!(syn::parse_file(&source).is_ok());
+        let source = compact(&source);
+        assert!(source.contains("builder.match_kind(rejects::options::MatchKind::LeftmostFirst);"));
+        assert!(source.contains("builder.anchored(true);"));
+    }
+
+    #[test]
+    fn test_builder_options() {
+        // a|ab
+        let chars = |c| [c].iter().cloned().collect::<HashSet<char>>();
+        let mut builder = Builder::new(0);
+        builder.with_split(1, Some(2));
+        builder.with_transition(chars('a'), HashSet::new(), Some(4));
+        builder.with_transition(chars('a'), HashSet::new(), Some(3));
+        builder.with_transition(chars('b'), HashSet::new(), Some(4));
+        builder.with_match();
+        let re = builder.build();
+        let found: Vec<&str> = re.find_iter("abab").map(|m| m.as_str()).collect();
+        assert_eq!(found, vec!["ab", "ab"]);
+
+        let mut builder = Builder::new(0);
+        builder.with_split(1, Some(2));
+        builder.with_transition(chars('a'), HashSet::new(), Some(4));
+        builder.with_transition(chars('a'), HashSet::new(), Some(3));
+        builder.with_transition(chars('b'), HashSet::new(), Some(4));
+        builder.with_match();
+        builder.match_kind(MatchKind::LeftmostFirst);
+        builder.anchored(true);
+        let re = builder.build();
+        let found: Vec<&str> = re.find_iter("abab").map(|m| m.as_str()).collect();
+        assert_eq!(found, vec!["a"]);
+    }
+
+    #[test]
+    fn test_char_slices() {
+        for regex in &[r"\d+-", r"a.", "é+x?", "(ab)*", "a*"] {
+            let re = Rejects::new(regex).unwrap();
+            for s in &["", "12-34", "a", "ab", "a\n", "éé", "ééx", "ababa", "b"] {
+                let chars: Vec<char> = s.chars().collect();
+                assert_eq!(re.is_match_chars(&chars), re.is_match(s), "{} {}", regex, s);
+                assert_eq!(re.find_end_chars(&chars), re.find_end(s), "{} {}", regex, s);
+            }
+        }
+        // indices are of characters, not bytes
+        let re = Rejects::new("é+").unwrap();
+        assert_eq!(re.find_end_chars(&['é', 'é', 'x']), 1);
+        assert!(re.is_match_chars(&['é']));
+        assert!(!re.is_match_chars(&[]));
+    }
+
+    #[test]
+    fn test_leftmost_first_matches_pcre2() {
+        // the first match of each pattern as reported by PCRE2
+        let cases = [
+            (r"(a|ab)c", "abc", "abc"),
+            (r"(a|ab)(c|b)", "abc", "ab"),
+            (r"a|ab", "ab", "a"),
+            (r"ab|a", "ab", "ab"),
+            (r"a*(ab)?", "aab", "aa"),
+            (r"(a|ab)*c", "ababc", "ababc"),
+            (r"(ab|a)(bc|c)?", "abc", "abc"),
+            (r"x(a|ab)", "xabx", "xa"),
+            (r"(a+|b)+", "aab", "aab"),
+        ];
+        let builder = Rejects::builder().match_kind(MatchKind::LeftmostFirst);
+        for (regex, s, expected) in cases.iter() {
+            let re = builder.compile(regex).unwrap();
+            let found = re.find_iter(s).next().map(|m| m.as_str());
+            assert_eq!(found, Some(*expected), "{} {}", regex, s);
+        }
+
+        let longest = Rejects::new(r"(a|ab)(c|b)").unwrap();
+        assert_eq!(longest.find_iter("abc").next().unwrap().as_str(), "abc");
+    }
+
+    #[test]
+    fn test_try_compile_all() {
+        let results = Rejects::try_compile_all(&["a+", "(b", "c|d", "*", "[[:nope:]]"]);
+        assert_eq!(results.len(), 5);
+        assert!(results[0].as_ref().unwrap().is_match("aa"));
+        assert!(results[1].is_err());
+        assert!(results[2].as_ref().unwrap().is_match("d"));
+        assert_eq!(
+            results[3].as_ref().unwrap_err(),
+            &vec![ParseError {
+                index: 0,
+                kind: ParseErrorKind::Unexpected
+            }]
+        );
+        assert!(results[4].is_err());
+        assert!(Rejects::try_compile_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_find_first() {
+        for regex in &["abc", "b+", "x*", "é|c", "[^a]c"] {
+            let re = Rejects::new(regex).unwrap();
+            for s in &["", "abc", "xxabcabc", "aé", "bbcb", "cc"] {
+                assert_eq!(re.find_first(s), re.find_iter(s).next(), "{} {}", regex, s);
+            }
+        }
+        let re = Rejects::new("b+").unwrap();
+        let m = re.find_first("abbcb").unwrap();
+        assert_eq!((m.start(), m.end(), m.as_str()), (1, 3, "bb"));
+
+        // nothing after the first match is looked at
+        let furthest = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::clone(&furthest);
+        let re = Rejects::new("ab")
+            .unwrap()
+            .with_prefilter(move |s: &str, from: usize| {
+                seen.fetch_max(from, Ordering::SeqCst);
+                s[from..].find('a').map(|i| from + i)
+            });
+        let s = format!("xab{}", "ab".repeat(1000));
+        assert_eq!(re.find_first(&s).map(|m| m.start()), Some(1));
+        assert_eq!(furthest.load(Ordering::SeqCst), 0);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        let regex = Rejects::new("a(b|c)*d").unwrap();
+        let loaded = Rejects::from_json(&regex.to_json()).unwrap();
+        assert_eq!(loaded.state_count(), regex.state_count());
+        for s in &["ad", "abcbd", "a", "abx", ""] {
+            assert_eq!(loaded.is_match(s), regex.is_match(s));
+            assert_eq!(loaded.find_end(s), regex.find_end(s));
+        }
+        assert_eq!(loaded.find_iter("xabdxacd").count(), 2);
+
+        // the options the machine searches with are kept
+        let options = Options {
+            match_kind: MatchKind::LeftmostFirst,
+            anchored: true,
+            ..Options::default()
+        };
+        let regex = Rejects::with_options("a|ab", &options).unwrap();
+        let loaded = Rejects::from_json(&regex.to_json()).unwrap();
+        let found = |re: &Rejects, s| -> Vec<(usize, usize)> {
+            re.find_iter(s).map(|m| (m.start(), m.end())).collect()
+        };
+        for s in &["ab", "abab", "xab", "aab"] {
+            assert_eq!(found(&loaded, s), found(&regex, s), "{}", s);
+        }
+        assert_eq!(found(&loaded, "abab"), [(0, 1)]);
+        assert_eq!(found(&loaded, "xab"), []);
+        // machines saved without them search with the defaults
+        let json = regex.to_json();
+        let json = json.replace(r#","match_kind":"LeftmostFirst","anchored":true"#, "");
+        assert!(!json.contains("anchored"));
+        let loaded = Rejects::from_json(&json).unwrap();
+        assert_eq!(found(&loaded, "abab"), [(0, 2), (2, 4)]);
+        assert_eq!(found(&loaded, "xab"), [(1, 3)]);
+
+        // indices are checked on load
+        assert!(Rejects::from_json(r#"{"start":1,"states":["Match"]}"#).is_err());
+        assert!(Rejects::from_json(
+            r#"{"start":0,"states":[{"Split":{"out1":0,"out2":3}},"Match"]}"#
+        )
+        .is_err());
+        assert!(Rejects::from_json(r#"{"start":0,"states":["Match"]}"#).is_ok());
+        assert!(Rejects::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn test_anchored_find_iter() {
+        let re = Rejects::new(r"\d+|\+").unwrap();
+        let tokens: Vec<&str> = re.anchored_find_iter("12+34").map(|m| m.as_str()).collect();
+        assert_eq!(tokens, vec!["12", "+", "34"]);
+        // the space doesn't match so "5" is never reached, unlike with find_iter
+        let tokens: Vec<&str> = re
+            .anchored_find_iter("12+34 5")
+            .map(|m| m.as_str())
+            .collect();
+        assert_eq!(tokens, vec!["12", "+", "34"]);
+        assert_eq!(re.find_iter("12+34 5").count(), 4);
+        assert_eq!(re.anchored_find_iter(" 12").count(), 0);
+        // an empty match ends the run too
+        assert_eq!(
+            Rejects::new("a*")
+                .unwrap()
+                .anchored_find_iter("aab")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_negated_newline_class() {
+        let newline: HashSet<char> = ['\n'].iter().copied().collect();
+        assert_eq!(
+            parse_ast(r"[^\n]").unwrap(),
+            Ast::Class {
+                inclusive: HashSet::new(),
+                exclusive: newline,
+            }
+        );
+
+        let re = Rejects::new(r"[^\n]+").unwrap();
+        assert_eq!(re.find_end("héllo wörld ✓\nnext"), 12);
+        let lines: Vec<&str> = re
+            .find_iter("one\r\ntwo\n\nthree")
+            .map(|m| m.as_str())
+            .collect();
+        assert_eq!(lines, vec!["one\r", "two", "three"]);
+        assert!(!re.is_match("a\nb"));
+
+        assert!(Rejects::new(r"a\tb").unwrap().is_match("a\tb"));
+        assert!(Rejects::new(r"[\r\n]+").unwrap().is_match("\r\n"));
+        assert!(Rejects::new(r"\q").is_err());
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        let prefix = |regex| Rejects::new(regex).unwrap().literal_prefix();
+        assert_eq!(prefix(r"http://\w+"), Some("http://".to_string()));
+        assert_eq!(prefix("abc"), Some("abc".to_string()));
+        assert_eq!(prefix("abc|abd"), Some("ab".to_string()));
+        assert_eq!(prefix("ab*c"), Some("a".to_string()));
+        assert_eq!(prefix("[ab]c"), None);
+        assert_eq!(prefix("a|b"), None);
+        assert_eq!(prefix("(?i)ab"), None);
+
+        let mut builder = Builder::new(0);
+        builder.with_transition(['a'].iter().copied().collect(), HashSet::new(), Some(0));
+        assert_eq!(builder.build().literal_prefix(), Some("a".to_string()));
+    }
+
+    #[cfg(feature = "nfc")]
+    #[test]
+    fn test_nfc() {
+        let decomposed = "cafe\u{301}";
+        let re = Rejects::builder().nfc(true).compile("caf\u{e9}").unwrap();
+        assert!(re.is_match(decomposed));
+        assert!(re.is_match(decomposed));
+        assert!(re.contains("a cafe\u{301} au lait"));
+        assert!(re.is_match("caf\u{e9}"));
+        // a decomposed pattern is normalized too
+        let re = Rejects::builder().nfc(true).compile("cafe\u{301}").unwrap();
+        assert!(re.is_match("caf\u{e9}"));
+        let chars: Vec<char> = decomposed.chars().collect();
+        assert!(re.is_match_chars(&chars));
+        assert!(re.is_suffix_match("a cafe\u{301}"));
+        // only the parsed characters are normalized, so what the operators apply to is kept
+        let re = Rejects::builder().nfc(true).compile("e\u{301}*").unwrap();
+        assert!(re.is_match("e"));
+        let re = Rejects::builder().nfc(true).compile("[e\u{301}]").unwrap();
+        assert!(re.is_match("e"));
+        assert_eq!(
+            Rejects::builder()
+                .nfc(true)
+                .compile("cafe\u{301}(")
+                .unwrap_err()[0]
+                .index,
+            6
+        );
+
+        assert!(!Rejects::new("caf\u{e9}").unwrap().is_match(decomposed));
+    }
+
+    #[test]
+    fn test_is_match_until() {
+        let re = Rejects::new("a*b").unwrap();
+        let long = "a".repeat(10_000);
+        let past = std::time::Instant::now();
+        assert_eq!(re.is_match_until(&long, past), Err(Timeout));
+        // decided before the clock is first looked at
+        assert_eq!(re.is_match_until("aab", past), Ok(true));
+        assert_eq!(re.is_match_until("aac", past), Ok(false));
+
+        let later = std::time::Instant::now() + std::time::Duration::from_secs(60);
+        assert_eq!(re.is_match_until(&long, later), Ok(false));
+        assert_eq!(re.is_match_until(&(long + "b"), later), Ok(true));
+    }
+
+    #[test]
+    fn test_matching_lines() {
+        let re = Rejects::new(r"b\d").unwrap();
+        let text = "a1 b2\r\nc3\n\nb4 d5\r\nb\nend b6";
+        assert_eq!(
+            re.matching_lines(text),
+            vec![(1, "a1 b2"), (4, "b4 d5"), (6, "end b6")]
+        );
+        assert_eq!(re.matching_lines("b1\n"), vec![(1, "b1")]);
+        assert!(re.matching_lines("").is_empty());
+        // an empty match counts, so every line is reported
+        assert_eq!(
+            Rejects::new("x*").unwrap().matching_lines("a\n\nb").len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_all_longest_matches() {
+        let re = Rejects::new("ab*|b").unwrap();
+        assert_eq!(
+            re.all_longest_matches("abbxab"),
+            vec![(0, 3), (1, 2), (2, 3), (4, 6), (5, 6)]
+        );
+        // the same longest matches under LeftmostFirst
+        let options = Options {
+            match_kind: MatchKind::LeftmostFirst,
+            ..Options::default()
+        };
+        let re = Rejects::with_options("a|ab", &options).unwrap();
+        assert_eq!(re.all_longest_matches("ab"), vec![(0, 2)]);
+        assert_eq!(
+            Rejects::new("é*").unwrap().all_longest_matches("éx"),
+            vec![(0, 2), (2, 2)]
+        );
+        assert!(Rejects::new("a")
+            .unwrap()
+            .all_longest_matches("")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_leading_bracket_in_class() {
+        let re = Rejects::new("[]]").unwrap();
+        assert!(re.is_match("]"));
+        assert!(!re.is_match("a"));
+        let re = Rejects::new("[^]]").unwrap();
+        assert!(!re.is_match("]"));
+        assert!(re.is_match("a"));
+        assert!(re.is_match("é"));
+        let re = Rejects::new("[]a]+").unwrap();
+        assert!(re.is_match("a]]a"));
+        assert!(!re.is_match("a]b"));
+        // only the first ']' is a member
+        assert!(Rejects::new("[]]]").unwrap().is_match("]]"));
+        assert!(Rejects::new("[]").is_err());
+        assert!(Rejects::new("[^]").is_err());
+    }
+
+    #[test]
+    fn test_find() {
+        let re = Rejects::new("a+").unwrap();
+        assert_eq!(re.find("aaab"), Some("aaa"));
+        assert_eq!(re.find("baaa"), None);
+        assert_eq!(re.find(""), None);
+        assert_eq!(Rejects::new("a*").unwrap().find("b"), Some(""));
+        // sliced on a character boundary, not at the character index find_end gives
+        let re = Rejects::new("é+x?").unwrap();
+        assert_eq!(re.find("ééxé"), Some("ééx"));
+        assert_eq!(re.find_end("ééxé"), 2);
+        assert_eq!(Rejects::new("a.").unwrap().find("a"), None);
+    }
+
+    #[test]
+    fn test_find_end_with_limit() {
+        let re = Rejects::new("abc").unwrap();
+        assert_eq!(re.find_end_with_limit("abcd", 1), Ok(2));
+
+        let re = Rejects::new("(a|b)*c").unwrap();
+        let s = "abba".repeat(100) + "c";
+        assert_eq!(re.find_end_with_limit(&s, 100), Ok(re.find_end(&s)));
+        assert_eq!(
+            re.find_end_with_limit(&s, 2),
+            Err(TooManyActive { limit: 2 })
+        );
+        assert_eq!(
+            re.find_end_with_limit("", 2),
+            Err(TooManyActive { limit: 2 })
+        );
+        assert_eq!(
+            Rejects::new("a|b").unwrap().find_end_with_limit("", 3),
+            Ok(-1)
+        );
+    }
+
+    #[test]
+    fn test_range_endpoints() {
+        let re = Rejects::new("[a-c]").unwrap();
+        assert_eq!(re.find_end("a"), 0);
+        assert_eq!(re.find_end("c"), 0);
+        assert_eq!(re.find_end("d"), -1);
+        let re = Rejects::new("[0-9]+").unwrap();
+        assert_eq!(re.find_end("9"), 0);
+        assert_eq!(re.find_end("0189x"), 3);
+        assert_eq!(Rejects::new("[a-z]").unwrap().find_end("z"), 0);
+        // a range of one character
+        assert_eq!(Rejects::new("[a-a]").unwrap().find_end("a"), 0);
+        assert!(Rejects::new("[1-9]").is_ok());
+
+        // ranges go by code point, whichever end isn't ASCII
+        let re = Rejects::new("[a-é]").unwrap();
+        assert!(re.is_match("a"));
+        assert!(re.is_match("z"));
+        assert!(re.is_match("é"));
+        assert!(!re.is_match("ê"));
+        assert!(!re.is_match("-"));
+        let re = Rejects::new("[a-š]").unwrap();
+        assert!(re.is_match("é"));
+        assert!(re.is_match("š"));
+        assert!(!re.is_match("ţ"));
+        let re = Rejects::new("[α-γ]+").unwrap();
+        assert!(re.is_match("αβγ"));
+        assert!(!re.is_match("δ"));
+        assert!(Rejects::new("[š-a]").is_err());
+    }
+
+    #[test]
+    fn test_warnings() {
+        let warnings = |regex| Rejects::with_warnings(regex).unwrap().1;
+        let (re, found) = Rejects::with_warnings("a|a").unwrap();
+        assert!(re.is_match("a"));
+        assert_eq!(
+            found,
+            vec![Warning {
+                index: 0,
+                kind: WarningKind::DuplicateAlternative,
+            }]
+        );
+        assert_eq!(warnings("b|ab|ab")[0].index, 2);
+        assert_eq!(
+            warnings("(a|b)|(a|b)")[0].kind,
+            WarningKind::DuplicateAlternative
+        );
+        assert_eq!(warnings("[a-a]")[0].kind, WarningKind::RedundantRange);
+        // indices after a class or an escape count all of their characters
+        assert_eq!(warnings("x[a-a]")[0].index, 1);
+        assert_eq!(warnings("[^ab-b]")[0].index, 0);
+        assert_eq!(warnings("[ab]|c|c")[0].index, 5);
+        assert_eq!(warnings("[[:alpha:]]|c|c")[0].index, 12);
+        assert_eq!(warnings(r"\d|c|c")[0].index, 3);
+        assert_eq!(warnings("[a-z-[aeiou]](b?)*")[0].index, 17);
+        assert_eq!(Rejects::new("[ab]{0}").unwrap_err()[0].index, 6);
+        assert_eq!(
+            warnings("b(a?)*"),
+            vec![Warning {
+                index: 5,
+                kind: WarningKind::EmptyRepetition,
+            }]
+        );
+        assert!(warnings("a|b|ab").is_empty());
+        assert!(warnings("(a|b)*[a-z]").is_empty());
+        assert!(Rejects::with_warnings("a|(").is_err());
+    }
+
+    #[test]
+    fn test_whitespace_class() {
+        let re = Rejects::new(r"\s").unwrap();
+        for c in &[" ", "\t", "\n", "\r", "\x0c", "\x0b"] {
+            assert!(re.is_match(c), "{:?}", c);
+        }
+        assert!(!re.is_match("a"));
+        let re = Rejects::new(r"\S").unwrap();
+        assert!(!re.is_match("\n"));
+        assert!(!re.is_match("\x0b"));
+        assert!(re.is_match("a"));
+        assert!(Rejects::new(r"a\s+b").unwrap().is_match("a \r\n\tb"));
+        assert!(Rejects::new(r"[^\s]+").unwrap().is_match("ab"));
+        assert!(!Rejects::new(r"[\S]").unwrap().is_match("\r"));
+    }
+
+    #[test]
+    fn test_is_partial_match() {
+        let re = Rejects::new(r"\d\d\d\d-\d\d-\d\d").unwrap();
+        assert!(re.is_partial_match("2010-0"));
+        assert!(!re.is_match("2010-0"));
+        assert!(re.is_partial_match(""));
+        assert!(re.is_partial_match("2010-01-02"));
+        assert!(!re.is_partial_match("2010-0x"));
+        assert!(!re.is_partial_match("2010-01-023"));
+        assert!(!re.is_partial_match("201-"));
+    }
+
+    #[test]
+    fn test_epsilon_cycles() {
+        for regex in &["(a*)*", "(a?)*", "(a?b?)*", "((a*)*)+", "(a*|b)*"] {
+            let re = Rejects::new(regex).unwrap();
+            assert_eq!(re.find_end("aaa"), 2, "{}", regex);
+            assert!(re.is_match(""), "{}", regex);
+        }
+        assert_eq!(Rejects::new("(a?b?)*c").unwrap().find_end("abbac"), 4);
+
+        // two Splits pointing at each other, which the optimizer can't remove
+        let mut builder = Builder::new(0);
+        builder.with_split(1, Some(2));
+        builder.with_split(0, Some(3));
+        builder.with_transition(['a'].iter().copied().collect(), HashSet::new(), Some(0));
+        builder.with_match();
+        let re = builder.build();
+        assert_eq!(re.find_end("aa"), 1);
+        assert!(re.is_match(""));
+    }
+
+    #[test]
+    fn test_counted_repetition() {
+        let re = Rejects::new("a{3}").unwrap();
+        assert!(re.is_match("aaa"));
+        assert!(!re.is_match("aa"));
+        assert!(!re.is_match("aaaa"));
+
+        let re = Rejects::new("a{2,}").unwrap();
+        assert!(!re.is_match("a"));
+        assert!(re.is_match("aa"));
+        assert!(re.is_match(&"a".repeat(50)));
+
+        let re = Rejects::new("a{1,3}").unwrap();
+        assert!(!re.is_match(""));
+        for s in &["a", "aa", "aaa"] {
+            assert!(re.is_match(s));
+        }
+        assert!(!re.is_match("aaaa"));
+        assert_eq!(re.find_end("aaaa"), 2);
+
+        assert!(Rejects::new("a{0,}").unwrap().is_match(""));
+        assert!(Rejects::new("a{0,2}").unwrap().is_match(""));
+        assert!(!Rejects::new("a{0,2}").unwrap().is_match("aaa"));
+        let re = Rejects::new(r"(ab|c){2}\d{1,2}").unwrap();
+        assert!(re.is_match("abc12"));
+        assert!(!re.is_match("ab1"));
+        assert_eq!(
+            Rejects::new(r"\d{4}-\d{2}")
+                .unwrap()
+                .find_iter("on 2010-01 and 99-1")
+                .count(),
+            1
+        );
+
+        for regex in &[
+            "a{2,1}",
+            "a{,}",
+            "a{,2}",
+            "a{}",
+            "a{0}",
+            "a{2",
+            "a{x}",
+            "a*{2}",
+            "a{99999999999999999999}",
+        ] {
+            assert!(Rejects::new(regex).is_err(), "{}", regex);
+        }
+        // the copies are capped by Options::max_repetition, counting nested repetitions written out
+        assert!(Rejects::new("a{10}").is_ok());
+        let re = Rejects::new("a{1000}").unwrap();
+        assert!(re.is_match(&"a".repeat(1000)));
+        assert!(!re.is_match(&"a".repeat(999)));
+        for regex in &["a{100000}", "a{1001}", "(ab){501}", "((a{100}){100}){100}"] {
+            assert_eq!(
+                Rejects::new(regex).unwrap_err()[0].kind,
+                ParseErrorKind::RepetitionTooLarge,
+                "{}",
+                regex
+            );
+        }
+        assert_eq!(Rejects::new("ba{100000}").unwrap_err()[0].index, 2);
+        let re = Rejects::builder().max_repetition(3).compile("a{4}");
+        assert_eq!(re.unwrap_err()[0].kind, ParseErrorKind::RepetitionTooLarge);
+        assert!(Rejects::builder().max_repetition(3).compile("a{3}").is_ok());
+
+        // a '{' which doesn't follow anything is the character
+        assert!(Rejects::new("{a").unwrap().is_match("{a"));
+        assert!(Rejects::new(r"a\{2}").unwrap().is_match("a{2}"));
+        assert_eq!(
+            parse_ast(r"(ab){2,3}c{4,}d{5}").unwrap().to_string(),
+            r"(ab){2,3}c{4,}d{5}"
+        );
+        assert_eq!(
+            Rejects::new(r"a\{").unwrap().to_regex_string(),
+            Some(r"a\{".to_string())
+        );
+    }
+
+    #[test]
+    fn test_explain_nomatch() {
+        let re = Rejects::new(r"\d{4}-\d{2}-\d{2}").unwrap();
+        assert_eq!(re.explain_nomatch("2010-01-05"), None);
+
+        let digits: HashSet<char> = ('0'..='9').collect();
+        let reason = re.explain_nomatch("2010-1x-05").unwrap();
+        assert_eq!(reason.position, 6);
+        assert_eq!(reason.expected, digits);
+        assert!(!reason.any_other);
+        assert!(!reason.end);
+
+        // ran out of text
+        let reason = re.explain_nomatch("2010-01").unwrap();
+        assert_eq!(reason.position, 7);
+        assert_eq!(reason.expected, ['-'].iter().copied().collect());
+        // went on too long
+        let reason = re.explain_nomatch("2010-01-055").unwrap();
+        assert_eq!(reason.position, 10);
+        assert!(reason.expected.is_empty());
+        assert!(reason.end);
+
+        let reason = Rejects::new("é(a|[^b])")
+            .unwrap()
+            .explain_nomatch("éb")
+            .unwrap();
+        assert_eq!(reason.position, 2);
+        assert!(reason.expected.contains(&'a'));
+        assert!(reason.any_other);
+    }
+
+    #[test]
+    fn test_anchors() {
+        let re = Rejects::new(r"^abc$").unwrap();
+        assert!(re.is_match("abc"));
+        assert!(!re.is_match("abcd"));
+        assert!(re.contains("abc"));
+        assert!(!re.contains("abcd"));
+        assert!(!re.contains("xabc"));
+        assert_eq!(re.find_end("abc"), 2);
+        assert_eq!(re.find_end("abcd"), -1);
+
+        // '^' only holds at the start of the input and '$' at its end, wherever the search starts
+        let starts = |re: &Rejects, s| re.find_iter(s).map(|m| m.start()).collect::<Vec<_>>();
+        assert_eq!(starts(&Rejects::new(r"^a").unwrap(), "aaa"), vec![0]);
+        assert_eq!(
+            Rejects::new(r"^a").unwrap().match_starts("aa"),
+            vec![true, false]
+        );
+        assert_eq!(
+            Rejects::new(r"a$").unwrap().match_starts("aa"),
+            vec![false, true]
+        );
+        let re = Rejects::new(r"a$").unwrap();
+        assert_eq!(starts(&re, "aaa"), vec![2]);
+        assert!(re.starts_with_match("a"));
+        assert!(!re.starts_with_match("ab"));
+        assert_eq!(re.run_to_completion("a"), vec![1]);
+        assert!(re.is_suffix_match("ba"));
+        assert!(!re.is_suffix_match("ab"));
+        let mut matcher = re.matcher();
+        matcher.feed("a");
+        assert!(matcher.is_match());
+        matcher.feed("b");
+        assert!(!matcher.is_match());
+        assert!(matcher.is_dead());
+        assert!(!Rejects::new(r"a^b").unwrap().contains("ab"));
+        assert!(Rejects::new(r"$^").unwrap().is_match(""));
+        // an optional anchor can be skipped anywhere
+        assert!(Rejects::new(r"(^|b)?a").unwrap().contains("ca"));
+        assert!(Rejects::new(r"a(b|$)?").unwrap().contains("ac"));
+        assert!(Rejects::new(r"$").unwrap().contains("ab"));
+        assert!(Rejects::new(r"$").unwrap().is_match(""));
+        // `\z` only holds at the end, `\Z` also before a '\n' which ends the input
+        let spans = |re: &Rejects, s| -> Vec<(usize, usize)> {
+            re.find_iter(s).map(|m| (m.start(), m.end())).collect()
+        };
+        let end = Rejects::new(r"abc\z").unwrap();
+        assert_eq!(spans(&end, "abc\n"), []);
+        assert_eq!(spans(&end, "abc"), [(0, 3)]);
+        let end = Rejects::new(r"abc\Z").unwrap();
+        assert_eq!(spans(&end, "abc\n"), [(0, 3)]);
+        assert_eq!(spans(&end, "abc"), [(0, 3)]);
+        assert_eq!(spans(&end, "abc\n\n"), []);
+        assert_eq!(spans(&end, "abc\nd"), []);
+        assert_eq!(end.find_end("abc\n"), 2);
+        assert!(!end.is_match("abc\n"));
+        assert_eq!(end.to_regex_string().unwrap(), r"abc\Z");
+        let first = Options {
+            match_kind: MatchKind::LeftmostFirst,
+            ..Options::default()
+        };
+        let end = Rejects::with_options(r"abc\Z", &first).unwrap();
+        assert_eq!(spans(&end, "abc\n"), [(0, 3)]);
+        assert_eq!(spans(&end, "abc\n\n"), []);
+        let end = Rejects::new(r"a\Z\n").unwrap();
+        assert!(end.is_match("a\n"));
+        assert!(end.is_match_chars(&['a', '\n']));
+        assert!(end.is_suffix_match("ba\n"));
+        let mut m = end.matcher();
+        m.feed("a\n");
+        assert!(m.is_match());
+        m.feed("\n");
+        assert!(m.is_dead());
+        for (pat, index) in &[
+            (r"^*", 1),
+            (r"\Z*", 2),
+            (r"$+", 1),
+            (r"(^)*", 3),
+            (r"(?<a>^|$)+", 9),
+            (r"a^?", 2),
+            (r"b${2}", 2),
+        ] {
+            let errs = Rejects::new(pat).unwrap_err();
+            assert_eq!(
+                errs,
+                vec![ParseError {
+                    index: *index,
+                    kind: ParseErrorKind::QuantifierOnAssertion
+                }],
+                "{}",
+                pat
+            );
+        }
+        assert_eq!(
+            Rejects::new(r"^*").unwrap_err()[0].to_string(),
+            "quantifier on an anchor at 1"
+        );
+        assert_eq!(
+            starts(&Rejects::new(r"^a").unwrap().minimize(), "aaa"),
+            vec![0]
+        );
+
+        let first = Options {
+            match_kind: MatchKind::LeftmostFirst,
+            ..Options::default()
+        };
+        let re = Rejects::with_options(r"(a|ab)$", &first).unwrap();
+        assert_eq!(re.find_iter("xab").next().map(|m| m.as_str()), Some("ab"));
+
+        let set = RejectsSet::new(&[r"ab$", r"a"]).unwrap();
+        assert_eq!(set.longest_match("ab"), Some((0, 2)));
+        assert_eq!(set.longest_match("abc"), Some((1, 1)));
+
+        const A_END: StaticRejects = StaticRejects::new(
+            0,
+            &[
+                StaticState::Transition {
+                    inclusive: &['a'],
+                    exclusive: &[],
+                    out: Some(1),
+                },
+                StaticState::Anchor {
+                    anchor: Anchor::End,
+                    out: Some(2),
+                },
+                StaticState::Match,
+            ],
+        );
+        assert!(A_END.is_full_match("a"));
+        assert!(!A_END.starts_with_match("ab"));
+
+        // escaped they are the characters themselves
+        assert!(Rejects::new(r"\^\$").unwrap().is_match("^$"));
+        assert_eq!(parse_ast(r"^[$]\^$").unwrap().to_string(), r"^\$\^$");
+    }
+
+    #[test]
+    fn test_find_span() {
+        let re = Rejects::new(r"\d+").unwrap();
+        assert_eq!(re.find_span("abc123def"), Some((3, 6)));
+        assert_eq!(re.find_span("12"), Some((0, 2)));
+        assert_eq!(re.find_span("abc"), None);
+        // character indices, not byte offsets
+        assert_eq!(re.find_span("éé42"), Some((2, 4)));
+
+        // the longest match at the leftmost position, even under LeftmostFirst
+        let first = Options {
+            match_kind: MatchKind::LeftmostFirst,
+            ..Options::default()
+        };
+        let re = Rejects::with_options(r"a|ab", &first).unwrap();
+        assert_eq!(re.find_span("xxab"), Some((2, 4)));
+
+        assert_eq!(Rejects::new(r"a*").unwrap().find_span("bab"), Some((0, 0)));
+        assert_eq!(Rejects::new(r"$").unwrap().find_span("ab"), Some((2, 2)));
+        let anchored = Options {
+            anchored: true,
+            ..Options::default()
+        };
+        let re = Rejects::with_options(r"b", &anchored).unwrap();
+        assert_eq!(re.find_span("ab"), None);
+    }
+
+    #[test]
+    fn test_anchors_on_empty_input() {
+        // the empty input has a single boundary, which is both its start and its end
+        for pat in &[r"^$", r"^", r"$"] {
+            let re = Rejects::new(pat).unwrap();
+            assert!(re.is_match(""), "{}", pat);
+            assert!(re.contains(""), "{}", pat);
+            // only the empty prefix matches, which find_end reports as no match
+            assert_eq!(re.find_end(""), -1, "{}", pat);
+            // and find_iter only reports non-empty matches
+            assert_eq!(re.find_iter("").count(), 0, "{}", pat);
+        }
+        let re = Rejects::new(r"^$").unwrap();
+        assert!(!re.is_match("a"));
+        assert!(!re.contains("a"));
+    }
+
+    #[test]
+    fn test_new_full() {
+        let re = Rejects::new_full(r"\d+").unwrap();
+        assert!(re.is_match("123"));
+        assert!(!re.is_match("12a"));
+        assert!(re.contains("123"));
+        assert!(!re.contains("12a"));
+        assert!(!re.contains("a12"));
+        assert_eq!(re.find_iter("12a").count(), 0);
+        assert!(Rejects::new(r"\d+").unwrap().contains("12a"));
+        assert_eq!(re.to_regex_string().unwrap(), r"^(\d+)$");
+        let re = Rejects::new_full("a|bc").unwrap();
+        assert!(re.is_match("bc"));
+        assert!(!re.contains("abc"));
+        assert_eq!(Rejects::new_full("a(").unwrap_err()[0].index, 2);
+    }
+
     #[test]
     fn test_union() {}
 
@@ -0,0 +1,74 @@
+use crate::rejects::Rejects;
+use std::collections::HashSet;
+
+/// The indices of the NFA states a `Matcher` is in.
+pub type StateSet = HashSet<usize>;
+
+/// Where a machine is after being fed some input with `Rejects::resume`, so matching can
+/// carry on with more input later. It holds no reference to the machine, only the states it is
+/// in, and is only meaningful to the `Rejects` it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchState {
+    pub(crate) states: StateSet,
+    pub(crate) accepting: bool,
+    // the last character fed, for the anchors
+    pub(crate) prev: Option<char>,
+}
+
+impl MatchState {
+    /// returns true if all of the input fed so far is matched.
+    pub fn is_match(&self) -> bool {
+        self.accepting
+    }
+
+    /// returns true if no more input can lead to a match, because the machine has no states
+    /// left.
+    pub fn is_dead(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+/// A cursor which runs a `Rejects` over its input a piece at a time, see `Rejects::matcher`.
+///
+/// Feeding `"ab"` and then `"c"` leaves the cursor in the same states as feeding `"abc"`, which
+/// is anchored at the start like `find_end`.
+#[derive(Debug, Clone)]
+pub struct Matcher<'r> {
+    rejects: &'r Rejects,
+    state: MatchState,
+}
+
+impl<'r> Matcher<'r> {
+    pub(crate) fn new(rejects: &'r Rejects) -> Matcher<'r> {
+        Matcher {
+            rejects,
+            state: rejects.match_state(),
+        }
+    }
+
+    /// Runs the machine over `s`, continuing from wherever the previous input left it.
+    pub fn feed(&mut self, s: &str) {
+        self.rejects.resume(&mut self.state, s);
+    }
+
+    /// returns the states the machine is in after all of the input fed so far.
+    pub fn active_states(&self) -> &StateSet {
+        &self.state.states
+    }
+
+    /// returns true if all of the input fed so far is matched.
+    pub fn is_match(&self) -> bool {
+        self.state.is_match()
+    }
+
+    /// returns true if no more input can lead to a match, because the machine has no states
+    /// left.
+    pub fn is_dead(&self) -> bool {
+        self.state.is_dead()
+    }
+
+    /// returns the state the cursor is in, which can be resumed later without the cursor.
+    pub fn into_state(self) -> MatchState {
+        self.state
+    }
+}
@@ -0,0 +1,105 @@
+use crate::rejects::Rejects;
+
+/// A non-empty match of a pattern in some text, given as byte offsets into the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'t> {
+    text: &'t str,
+    start: usize,
+    end: usize,
+}
+
+impl<'t> Match<'t> {
+    pub(crate) fn new(text: &'t str, start: usize, end: usize) -> Match<'t> {
+        Match { text, start, end }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// returns the matched part of the text.
+    pub fn as_str(&self) -> &'t str {
+        &self.text[self.start..self.end]
+    }
+}
+
+/// An iterator over the matches of a pattern in some text, see `Rejects::find_iter`.
+#[derive(Debug)]
+pub struct Matches<'r, 't> {
+    rejects: &'r Rejects,
+    text: &'t str,
+    pos: usize,
+    // the last byte offset a non-empty match can start at, None if the pattern matches nothing
+    last_start: Option<usize>,
+    // every match is at least this many characters long
+    min_len: usize,
+    // only look for a match where the last one ended, see `Rejects::anchored_find_iter`
+    anchored: bool,
+}
+
+impl<'r, 't> Matches<'r, 't> {
+    pub(crate) fn new(rejects: &'r Rejects, text: &'t str) -> Matches<'r, 't> {
+        // matches are skipped unless they have at least one character
+        let min_len = rejects.min_len().map(|n| n.max(1));
+        let last_start = min_len.and_then(|n| text.char_indices().rev().nth(n - 1));
+        Matches {
+            rejects,
+            text,
+            pos: 0,
+            last_start: last_start.map(|(i, _)| i),
+            min_len: min_len.unwrap_or(1),
+            anchored: rejects.anchored,
+        }
+    }
+
+    pub(crate) fn anchored(rejects: &'r Rejects, text: &'t str) -> Matches<'r, 't> {
+        Matches {
+            anchored: true,
+            ..Matches::new(rejects, text)
+        }
+    }
+}
+
+impl<'r, 't> Iterator for Matches<'r, 't> {
+    type Item = Match<'t>;
+
+    fn next(&mut self) -> Option<Match<'t>> {
+        loop {
+            let last_start = self.last_start?;
+            let (start, end) = if self.anchored {
+                if self.pos > last_start {
+                    return None;
+                }
+                (self.pos, self.rejects.match_at(self.text, self.pos)?)
+            } else {
+                self.rejects.find_in(self.text, self.pos, last_start)?
+            };
+            if start < end {
+                self.pos = end;
+                return Some(Match {
+                    text: self.text,
+                    start,
+                    end,
+                });
+            }
+            // skip over empty matches, an anchored search can't skip past one
+            if self.anchored {
+                return None;
+            }
+            self.pos = end + self.text[end..].chars().next()?.len_utf8();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // every character takes at least one byte
+        let left = match self.last_start {
+            Some(last) if self.pos <= last => self.text.len() - self.pos,
+            _ => 0,
+        };
+        (0, Some(left / self.min_len))
+    }
+}
@@ -1,6 +1,7 @@
 use quote::quote;
 use quote::{ToTokens, TokenStreamExt};
 use std::collections::HashSet;
+use std::iter::Peekable;
 use std::ops::Index;
 
 #[allow(dead_code)]
@@ -12,6 +13,7 @@ pub(crate) struct Fragment {
 
 #[allow(dead_code)]
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum State {
     Transition {
         inclusive: HashSet<char>,
@@ -22,10 +24,117 @@ pub enum State {
         out1: usize,
         out2: Option<usize>,
     },
+    /// Moves on to `out` without reading a character, but only where `anchor` holds.
+    Anchor {
+        anchor: Anchor,
+        out: Option<usize>,
+    },
     Match,
     Nil,
 }
 
+/// Where in the input a `State::Anchor` lets the match through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Anchor {
+    /// `^`, only at the start of the input.
+    Start,
+    /// `$` or `\z`, only at the end of the input.
+    End,
+    /// `^` with `Options::multiline`, at the start of the input or after a '\n'.
+    LineStart,
+    /// `$` with `Options::multiline`, at the end of the input or before a '\n'.
+    LineEnd,
+    /// `\Z`, at the end of the input or before a '\n' which ends it.
+    EndBeforeNewline,
+}
+
+/// What comes after a position in the input, for the anchors which look ahead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Next {
+    /// the end of the input
+    End,
+    /// a character which isn't (or isn't known to be) a '\n' ending the input
+    Char(char),
+    /// a '\n' which is the last character of the input
+    FinalNewline,
+}
+
+impl Next {
+    /// returns what comes first in `rest`, the input after a position.
+    pub(crate) fn of(rest: &str) -> Next {
+        match rest.chars().next() {
+            None => Next::End,
+            Some(_) if rest == "\n" => Next::FinalNewline,
+            Some(c) => Next::Char(c),
+        }
+    }
+
+    /// Same as `Next::of` for `c` read from `rest`, an iterator over the characters after it.
+    /// Only a '\n' has to know whether it is the last character, so `rest` is only peeked at
+    /// after one.
+    pub(crate) fn peek<I: Iterator<Item = char>>(c: char, rest: &mut Peekable<I>) -> Next {
+        if c == '\n' && rest.peek().is_none() {
+            Next::FinalNewline
+        } else {
+            Next::Char(c)
+        }
+    }
+
+    /// returns the character, `None` at the end of the input.
+    pub(crate) fn char(self) -> Option<char> {
+        match self {
+            Next::End => None,
+            Next::Char(c) => Some(c),
+            Next::FinalNewline => Some('\n'),
+        }
+    }
+}
+
+impl Anchor {
+    /// returns true if the anchor lets the match through between `prev` and `next`, what is
+    /// either side of the position (`prev` is `None` at the start of the input).
+    pub(crate) fn holds(self, prev: Option<char>, next: Next) -> bool {
+        match self {
+            Anchor::Start => prev.is_none(),
+            Anchor::End => next == Next::End,
+            Anchor::LineStart => matches!(prev, None | Some('\n')),
+            Anchor::LineEnd => matches!(next.char(), None | Some('\n')),
+            Anchor::EndBeforeNewline => matches!(next, Next::End | Next::FinalNewline),
+        }
+    }
+
+    /// returns true if whether the anchor holds depends on the character after the position
+    /// rather than the one before it.
+    pub(crate) fn looks_ahead(self) -> bool {
+        matches!(
+            self,
+            Anchor::End | Anchor::LineEnd | Anchor::EndBeforeNewline
+        )
+    }
+}
+
+/// Callbacks for `Rejects::walk`, one per kind of `State`. Each is given the index of the state
+/// being visited and does nothing by default.
+pub trait StateVisitor {
+    fn visit_transition(
+        &mut self,
+        _index: usize,
+        _inclusive: &HashSet<char>,
+        _exclusive: &HashSet<char>,
+        _out: Option<usize>,
+    ) {
+    }
+
+    fn visit_split(&mut self, _index: usize, _out1: usize, _out2: Option<usize>) {}
+
+    fn visit_anchor(&mut self, _index: usize, _anchor: Anchor, _out: Option<usize>) {}
+
+    fn visit_match(&mut self, _index: usize) {}
+
+    fn visit_nil(&mut self, _index: usize) {}
+}
+
 #[allow(dead_code)]
 impl State {
     pub fn make_transition(
@@ -44,6 +153,10 @@ impl State {
         State::Split { out1, out2 }
     }
 
+    pub fn make_anchor(anchor: Anchor, out: Option<usize>) -> State {
+        State::Anchor { anchor, out }
+    }
+
     pub fn make_match() -> State {
         State::Match
     }
@@ -90,10 +203,60 @@ impl State {
                 out1: _,
                 ref mut out2,
             } => *out2 = Some(newout),
+            State::Anchor { ref mut out, .. } => *out = Some(newout),
             _ => {} // State::Match and State::Nil but this shouldn't be reached
         }
     }
 
+    /// returns a copy of this state with every out moved up by `by`, for when the state list it is
+    /// in is placed after `by` other states.
+    pub(crate) fn shifted(&self, by: usize) -> State {
+        match self {
+            State::Transition {
+                inclusive,
+                exclusive,
+                out,
+            } => State::Transition {
+                inclusive: inclusive.clone(),
+                exclusive: exclusive.clone(),
+                out: out.map(|out| out + by),
+            },
+            State::Split { out1, out2 } => State::make_split(out1 + by, out2.map(|out| out + by)),
+            State::Anchor { anchor, out } => State::make_anchor(*anchor, out.map(|out| out + by)),
+            State::Match => State::Match,
+            State::Nil => State::Nil,
+        }
+    }
+
+    /// Every state index this state can move to, either on a character or on epsilon.
+    pub(crate) fn outs(&self) -> Vec<usize> {
+        match self {
+            State::Transition { out, .. } | State::Anchor { out, .. } => {
+                out.iter().copied().collect()
+            }
+            State::Split { out1, out2 } => {
+                let mut outs = vec![*out1];
+                outs.extend(out2);
+                outs
+            }
+            State::Match | State::Nil => Vec::new(),
+        }
+    }
+
+    pub(crate) fn accept<V: StateVisitor>(&self, index: usize, v: &mut V) {
+        match self {
+            State::Transition {
+                inclusive,
+                exclusive,
+                out,
+            } => v.visit_transition(index, inclusive, exclusive, *out),
+            State::Split { out1, out2 } => v.visit_split(index, *out1, *out2),
+            State::Anchor { anchor, out } => v.visit_anchor(index, *anchor, *out),
+            State::Match => v.visit_match(index),
+            State::Nil => v.visit_nil(index),
+        }
+    }
+
     pub(crate) fn transition(&self, c: char) -> Option<usize> {
         match self {
             State::Transition {
@@ -163,6 +326,20 @@ impl ToTokens for State {
                     let state = rejects::nfa::State::make_split(#out1, out2);
                 });
             }
+            State::Anchor { anchor, out } => {
+                let anchor = anchor_tokens(*anchor);
+                match out {
+                    Some(n) => wrapper_stream.append_all(quote! {
+                        let out = Some(#n);
+                    }),
+                    None => wrapper_stream.append_all(quote! {
+                        let out: Option<usize> = None;
+                    }),
+                }
+                wrapper_stream.append_all(quote! {
+                    let state = rejects::nfa::State::make_anchor(#anchor, out);
+                });
+            }
             State::Match => {
                 wrapper_stream.append_all(quote! {
                     let state = rejects::nfa::State::make_match();
@@ -183,6 +360,62 @@ impl ToTokens for State {
     }
 }
 
+impl State {
+    /// Same as `to_tokens` but builds the `table::StaticState` for this state, which can be used
+    /// in a `const`.
+    pub(crate) fn to_static_tokens(&self) -> proc_macro2::TokenStream {
+        let out_tokens = |out: Option<usize>| match out {
+            Some(n) => quote! { Some(#n) },
+            None => quote! { None },
+        };
+        match self {
+            State::Transition {
+                inclusive,
+                exclusive,
+                out,
+            } => {
+                let mut inclusive: Vec<char> = inclusive.iter().copied().collect();
+                let mut exclusive: Vec<char> = exclusive.iter().copied().collect();
+                inclusive.sort_unstable();
+                exclusive.sort_unstable();
+                let out = out_tokens(*out);
+                quote! {
+                    rejects::table::StaticState::Transition {
+                        inclusive: &[#(#inclusive),*],
+                        exclusive: &[#(#exclusive),*],
+                        out: #out,
+                    }
+                }
+            }
+            State::Split { out1, out2 } => {
+                let out2 = out_tokens(*out2);
+                quote! {
+                    rejects::table::StaticState::Split { out1: #out1, out2: #out2 }
+                }
+            }
+            State::Anchor { anchor, out } => {
+                let anchor = anchor_tokens(*anchor);
+                let out = out_tokens(*out);
+                quote! {
+                    rejects::table::StaticState::Anchor { anchor: #anchor, out: #out }
+                }
+            }
+            State::Match => quote! { rejects::table::StaticState::Match },
+            State::Nil => quote! { rejects::table::StaticState::Nil },
+        }
+    }
+}
+
+fn anchor_tokens(anchor: Anchor) -> proc_macro2::TokenStream {
+    match anchor {
+        Anchor::Start => quote! { rejects::nfa::Anchor::Start },
+        Anchor::End => quote! { rejects::nfa::Anchor::End },
+        Anchor::LineStart => quote! { rejects::nfa::Anchor::LineStart },
+        Anchor::LineEnd => quote! { rejects::nfa::Anchor::LineEnd },
+        Anchor::EndBeforeNewline => quote! { rejects::nfa::Anchor::EndBeforeNewline },
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct StateList {
     pub(crate) states: Vec<State>,
@@ -193,63 +426,23 @@ impl StateList {
         StateList { states: Vec::new() }
     }
 
-    pub(crate) fn union(
-        &mut self,
-        f1opt: Option<Fragment>,
-        f2opt: Option<Fragment>,
-    ) -> Option<Fragment> {
-        let mut f1 = f1opt?;
-        let f2 = match f2opt {
-            Some(f) => f,
-            None => return Some(f1),
-        };
-
+    pub(crate) fn union(&mut self, mut f1: Fragment, f2: Fragment) -> Fragment {
         let start = self.add_state(State::make_split(f1.start, Some(f2.start)));
         f1.endstates.extend(f2.endstates);
-        Some(Fragment {
+        Fragment {
             start,
             endstates: f1.endstates,
-        })
+        }
     }
 
-    pub(crate) fn concatenation(
-        &mut self,
-        f1opt: Option<Fragment>,
-        f2opt: Option<Fragment>,
-    ) -> Option<Fragment> {
-        let f1 = match f1opt {
-            Some(f) => f,
-            None => return None,
-        };
-        let f2 = match f2opt {
-            Some(f) => f,
-            None => return Some(f1),
-        };
-
+    pub(crate) fn concatenation(&mut self, f1: Fragment, f2: Fragment) -> Fragment {
         for &dangler in f1.endstates.iter() {
             self.link(dangler, f2.start);
         }
 
-        Some(Fragment {
+        Fragment {
             start: f1.start,
             endstates: f2.endstates,
-        })
-    }
-
-    pub(crate) fn unary_operator(
-        &mut self,
-        f: Option<Fragment>,
-        op: Option<char>,
-    ) -> Option<Fragment> {
-        if let Some(frag) = f {
-            match op {
-                Some('*') => Some(self.kleene(frag)),
-                Some('?') => Some(self.question_mark(frag)),
-                Some('+') => Some(self.plus(frag)),
-                _ => Some(frag), // No operand so just return what we have
-            }
-        } else {
-            None
         }
     }
 
@@ -310,8 +503,8 @@ impl StateList {
         }
     }
 
-    pub(crate) fn non_characters(&mut self, chars: HashSet<char>) -> Fragment {
-        let state = self.add_state(State::make_exclusive_transition(chars));
+    pub(crate) fn anchor(&mut self, anchor: Anchor) -> Fragment {
+        let state = self.add_state(State::make_anchor(anchor, None));
         Fragment {
             start: state,
             endstates: vec![state],
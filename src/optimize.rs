@@ -0,0 +1,192 @@
+use crate::ast::Ast;
+
+/// Rewrites the tree into one which matches the same language with fewer states.
+pub(crate) fn optimize(ast: Ast) -> Ast {
+    factor_prefixes(simplify_quantifiers(ast))
+}
+
+/// Collapses quantifiers applied directly to quantifiers into one, so `(a+)*` becomes `a*`, and
+/// drops a `?` from an expression which already matches the empty string, so `(a*b*)?` becomes
+/// `a*b*`.
+fn simplify_quantifiers(ast: Ast) -> Ast {
+    match ast {
+        Ast::Star(ast) => match quantified(simplify_quantifiers(*ast)) {
+            Ast::Star(ast) | Ast::Plus(ast) | Ast::Optional(ast) => Ast::Star(ast),
+            ast => Ast::Star(Box::new(ast)),
+        },
+        Ast::Plus(ast) => match quantified(simplify_quantifiers(*ast)) {
+            Ast::Star(ast) | Ast::Optional(ast) => Ast::Star(ast),
+            Ast::Plus(ast) => Ast::Plus(ast),
+            ast => Ast::Plus(Box::new(ast)),
+        },
+        Ast::Optional(ast) => match quantified(simplify_quantifiers(*ast)) {
+            Ast::Star(ast) | Ast::Plus(ast) => Ast::Star(ast),
+            Ast::Optional(ast) => Ast::Optional(ast),
+            ast if nullable(&ast) => ast,
+            ast => Ast::Optional(Box::new(ast)),
+        },
+        Ast::Concat(l, r) => Ast::Concat(
+            Box::new(simplify_quantifiers(*l)),
+            Box::new(simplify_quantifiers(*r)),
+        ),
+        Ast::Union(l, r) => Ast::Union(
+            Box::new(simplify_quantifiers(*l)),
+            Box::new(simplify_quantifiers(*r)),
+        ),
+        Ast::Repeat { ast, min, max } => Ast::Repeat {
+            ast: Box::new(simplify_quantifiers(*ast)),
+            min,
+            max,
+        },
+        Ast::Group(ast) => Ast::Group(Box::new(simplify_quantifiers(*ast))),
+        Ast::NamedGroup(name, ast) => Ast::NamedGroup(name, Box::new(simplify_quantifiers(*ast))),
+        Ast::Literal(_) | Ast::Class { .. } | Ast::Anchor(_) => ast,
+    }
+}
+
+// Looks through plain groups for a quantifier, e.g. the `a*` in `((a*))`, returning `ast`
+// unchanged if there isn't one.
+fn quantified(ast: Ast) -> Ast {
+    match ast {
+        Ast::Group(ast) => match quantified(*ast) {
+            ast @ Ast::Star(_) | ast @ Ast::Plus(_) | ast @ Ast::Optional(_) => ast,
+            ast => Ast::Group(Box::new(ast)),
+        },
+        _ => ast,
+    }
+}
+
+// true if `ast` matches the empty string wherever it is tried. An anchor only matches it at some
+// positions, so `(^|a)?` still matches where `^|a` doesn't.
+pub(crate) fn nullable(ast: &Ast) -> bool {
+    match ast {
+        Ast::Literal(_) | Ast::Class { .. } | Ast::Anchor(_) => false,
+        Ast::Concat(l, r) => nullable(l) && nullable(r),
+        Ast::Union(l, r) => nullable(l) || nullable(r),
+        Ast::Star(_) | Ast::Optional(_) => true,
+        Ast::Repeat { ast, min, .. } => *min == 0 || nullable(ast),
+        Ast::Plus(ast) | Ast::Group(ast) | Ast::NamedGroup(_, ast) => nullable(ast),
+    }
+}
+
+/// Hoists the common prefix out of consecutive alternatives, so `abc|abd` becomes `ab(c|d)`.
+///
+/// Only neighbouring alternatives are merged and no alternative but the last in a run may be left
+/// empty, so the order the alternatives are tried in does not change.
+fn factor_prefixes(ast: Ast) -> Ast {
+    match ast {
+        Ast::Union(_, _) => {
+            let mut alts = Vec::new();
+            alternatives(ast, &mut alts);
+            let mut seqs: Vec<Vec<Ast>> = alts
+                .into_iter()
+                .map(|alt| {
+                    let mut seq = Vec::new();
+                    sequence(factor_prefixes(alt), &mut seq);
+                    seq
+                })
+                .collect();
+
+            let mut factored = Vec::new();
+            while !seqs.is_empty() {
+                let run = seqs
+                    .iter()
+                    .skip(1)
+                    .take_while(|seq| seq[0] == seqs[0][0])
+                    .count()
+                    + 1;
+                let run: Vec<Vec<Ast>> = seqs.drain(..run).collect();
+                let k = common_prefix_len(&run);
+                if run.len() == 1 || k == 0 {
+                    factored.extend(run.into_iter().map(concat_all));
+                    continue;
+                }
+
+                let mut seq = run[0][..k].to_vec();
+                let empty_last = run[run.len() - 1].len() == k;
+                let rests: Vec<Ast> = run
+                    .into_iter()
+                    .map(|mut seq| seq.split_off(k))
+                    .filter(|rest| !rest.is_empty())
+                    .map(concat_all)
+                    .collect();
+                let rest = Ast::Group(Box::new(factor_prefixes(union_all(rests))));
+                if empty_last {
+                    seq.push(Ast::Optional(Box::new(rest)));
+                } else {
+                    seq.push(rest);
+                }
+                factored.push(concat_all(seq));
+            }
+            union_all(factored)
+        }
+        Ast::Concat(l, r) => {
+            Ast::Concat(Box::new(factor_prefixes(*l)), Box::new(factor_prefixes(*r)))
+        }
+        Ast::Star(ast) => Ast::Star(Box::new(factor_prefixes(*ast))),
+        Ast::Plus(ast) => Ast::Plus(Box::new(factor_prefixes(*ast))),
+        Ast::Optional(ast) => Ast::Optional(Box::new(factor_prefixes(*ast))),
+        Ast::Repeat { ast, min, max } => Ast::Repeat {
+            ast: Box::new(factor_prefixes(*ast)),
+            min,
+            max,
+        },
+        Ast::Group(ast) => Ast::Group(Box::new(factor_prefixes(*ast))),
+        Ast::NamedGroup(name, ast) => Ast::NamedGroup(name, Box::new(factor_prefixes(*ast))),
+        Ast::Literal(_) | Ast::Class { .. } | Ast::Anchor(_) => ast,
+    }
+}
+
+// The length of the prefix shared by every sequence in `run`, short enough that only the last
+// sequence can be used up by it.
+fn common_prefix_len(run: &[Vec<Ast>]) -> usize {
+    let last = run.len() - 1;
+    let mut k = run
+        .iter()
+        .enumerate()
+        .map(|(i, seq)| if i == last { seq.len() } else { seq.len() - 1 })
+        .min()
+        .unwrap_or(0);
+    for (i, ast) in run[0].iter().enumerate().take(k) {
+        if run.iter().any(|seq| seq[i] != *ast) {
+            k = i;
+            break;
+        }
+    }
+    k
+}
+
+fn alternatives(ast: Ast, alts: &mut Vec<Ast>) {
+    match ast {
+        Ast::Union(l, r) => {
+            alternatives(*l, alts);
+            alternatives(*r, alts);
+        }
+        _ => alts.push(ast),
+    }
+}
+
+fn sequence(ast: Ast, seq: &mut Vec<Ast>) {
+    match ast {
+        Ast::Concat(l, r) => {
+            sequence(*l, seq);
+            sequence(*r, seq);
+        }
+        _ => seq.push(ast),
+    }
+}
+
+// Both of these expect at least one element.
+fn concat_all(seq: Vec<Ast>) -> Ast {
+    seq.into_iter()
+        .rev()
+        .fold(None, |ast, next| Ast::concatenation(Some(next), ast))
+        .unwrap()
+}
+
+fn union_all(alts: Vec<Ast>) -> Ast {
+    alts.into_iter()
+        .rev()
+        .fold(None, |ast, next| Ast::union(Some(next), ast))
+        .unwrap()
+}
@@ -0,0 +1,136 @@
+use crate::parser::ParseError;
+use crate::rejects::Rejects;
+use std::collections::HashSet;
+
+/// Options which change how a pattern is compiled, see `Rejects::with_options`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The characters which '.' does not match. Defaults to just '\n', an empty set makes '.'
+    /// match any character.
+    pub dot_excludes: HashSet<char>,
+    /// Which match searches such as `Rejects::find_iter` report. Defaults to
+    /// `MatchKind::LeftmostLongest`.
+    pub match_kind: MatchKind,
+    /// Whether the whole pattern matches case insensitively, as if it started with `(?i)`.
+    pub case_insensitive: bool,
+    /// Whether searches such as `Rejects::contains` and `Rejects::find_iter` only look for a
+    /// match where the search starts, instead of at every position after it. `find_iter` then
+    /// finds a run of matches, each starting where the last ended.
+    pub anchored: bool,
+    /// Whether `^` and `$` also match at the start and the end of each line, i.e. after and
+    /// before a '\n', instead of only at the start and the end of the input. The pattern given
+    /// back by `Rejects::to_regex_string` doesn't say so, it has to be compiled with this set
+    /// again.
+    pub multiline: bool,
+    /// The most states the compiled machine may have, patterns which need more fail to compile
+    /// with `ParseErrorKind::TooManyStates`.
+    pub max_states: Option<usize>,
+    /// The most characters and classes a counted repetition like `a{2,4}` may be written out
+    /// into, counting every copy it makes, patterns with a larger one fail to compile with
+    /// `ParseErrorKind::RepetitionTooLarge`. Defaults to 1000, so `a{1000}` compiles but
+    /// `(ab){501}` and `(a{100}){100}` don't.
+    pub max_repetition: usize,
+    /// Whether the pattern and the text are put in Unicode Normalization Form C before matching,
+    /// so canonically equivalent strings (e.g. "é" written as one character or as an 'e' and a
+    /// combining accent) match the same patterns. Only the characters of the parsed pattern are
+    /// normalized, runs of literals together and class members one by one, so the `*` in
+    /// `e\u{301}*` still only repeats the accent and `[e\u{301}]` still matches a lone 'e'. See `Rejects::contains` for which
+    /// searches normalize the text.
+    #[cfg(feature = "nfc")]
+    pub nfc: bool,
+}
+
+/// How a search chooses between the matches which start at the same place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MatchKind {
+    /// The longest of them (maximal munch), so `a|ab` finds `ab` in "ab".
+    LeftmostLongest,
+    /// The one a backtracking engine finds first: the alternatives of a union are tried from
+    /// left to right and `*`, `+` and `?` repeat as much as they can. `a|ab` finds `a` in "ab".
+    LeftmostFirst,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        let mut dot_excludes = HashSet::new();
+        dot_excludes.insert('\n');
+        Options {
+            dot_excludes,
+            match_kind: MatchKind::LeftmostLongest,
+            case_insensitive: false,
+            anchored: false,
+            multiline: false,
+            max_states: None,
+            max_repetition: 1000,
+            #[cfg(feature = "nfc")]
+            nfc: false,
+        }
+    }
+}
+
+/// Collects `Options` for compiling patterns, see `Rejects::builder`.
+#[derive(Debug, Clone, Default)]
+pub struct RejectsBuilder {
+    options: Options,
+}
+
+impl RejectsBuilder {
+    pub fn new() -> RejectsBuilder {
+        RejectsBuilder::default()
+    }
+
+    pub fn case_insensitive(mut self, yes: bool) -> RejectsBuilder {
+        self.options.case_insensitive = yes;
+        self
+    }
+
+    /// Whether '.' also matches a newline.
+    pub fn dotall(mut self, yes: bool) -> RejectsBuilder {
+        self.options.dot_excludes.clear();
+        if !yes {
+            self.options.dot_excludes.insert('\n');
+        }
+        self
+    }
+
+    pub fn multiline(mut self, yes: bool) -> RejectsBuilder {
+        self.options.multiline = yes;
+        self
+    }
+
+    pub fn anchored(mut self, yes: bool) -> RejectsBuilder {
+        self.options.anchored = yes;
+        self
+    }
+
+    pub fn max_states(mut self, n: usize) -> RejectsBuilder {
+        self.options.max_states = Some(n);
+        self
+    }
+
+    pub fn max_repetition(mut self, n: usize) -> RejectsBuilder {
+        self.options.max_repetition = n;
+        self
+    }
+
+    pub fn match_kind(mut self, match_kind: MatchKind) -> RejectsBuilder {
+        self.options.match_kind = match_kind;
+        self
+    }
+
+    #[cfg(feature = "nfc")]
+    pub fn nfc(mut self, yes: bool) -> RejectsBuilder {
+        self.options.nfc = yes;
+        self
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Compiles `pat` with the options set so far, the same as `Rejects::with_options`.
+    pub fn compile(&self, pat: &str) -> Result<Rejects, Vec<ParseError>> {
+        Rejects::with_options(pat, &self.options)
+    }
+}
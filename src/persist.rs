@@ -0,0 +1,96 @@
+use crate::nfa::State;
+use crate::options::MatchKind;
+use crate::rejects::Rejects;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+/// A `Rejects` is saved as its start state and state list along with the options it searches
+/// with (`Options::match_kind`, `Options::anchored` and `Options::nfc`). Whatever else was built
+/// alongside them when compiling (the syntax tree, literal automaton and prefilter) is not kept,
+/// the same as for one made with `Builder`. A machine saved without the options searches with
+/// the defaults.
+#[derive(serde::Serialize)]
+struct MachineRef<'a> {
+    start: usize,
+    states: &'a [State],
+    match_kind: MatchKind,
+    anchored: bool,
+    #[cfg(feature = "nfc")]
+    nfc: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct Machine {
+    start: usize,
+    states: Vec<State>,
+    #[serde(default = "default_match_kind")]
+    match_kind: MatchKind,
+    #[serde(default)]
+    anchored: bool,
+    #[cfg(feature = "nfc")]
+    #[serde(default)]
+    nfc: bool,
+}
+
+fn default_match_kind() -> MatchKind {
+    MatchKind::LeftmostLongest
+}
+
+impl Serialize for Rejects {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MachineRef {
+            start: self.start,
+            states: &self.statelist,
+            match_kind: self.match_kind,
+            anchored: self.anchored,
+            #[cfg(feature = "nfc")]
+            nfc: self.nfc,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rejects {
+    /// Fails if `start` or any `out`/`out1`/`out2` is not a valid state index.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Rejects, D::Error> {
+        let machine = Machine::deserialize(deserializer)?;
+        let (start, states) = (machine.start, machine.states);
+        let len = states.len();
+        if start >= len {
+            return Err(de::Error::custom(format!(
+                "start state {} is out of range ({} states)",
+                start, len
+            )));
+        }
+        for (i, state) in states.iter().enumerate() {
+            if let Some(out) = state.outs().into_iter().find(|&out| out >= len) {
+                return Err(de::Error::custom(format!(
+                    "state {} points to state {} which is out of range ({} states)",
+                    i, out, len
+                )));
+            }
+        }
+        let mut rejects = Rejects::from(start, states);
+        rejects.match_kind = machine.match_kind;
+        rejects.anchored = machine.anchored;
+        #[cfg(feature = "nfc")]
+        {
+            rejects.nfc = machine.nfc;
+        }
+        Ok(rejects)
+    }
+}
+
+#[cfg(feature = "json")]
+impl Rejects {
+    /// returns the machine as JSON, which `from_json` turns back into an equivalent `Rejects`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a Rejects can always be written as JSON")
+    }
+
+    /// Fails if `s` is not a machine written by `to_json`, or it has states pointing outside the
+    /// state list.
+    pub fn from_json(s: &str) -> Result<Rejects, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+}
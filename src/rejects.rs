@@ -1,13 +1,95 @@
-use crate::nfa::State;
-use crate::parser;
+use crate::aho_corasick::AhoCorasick;
+use crate::ast::Ast;
+use crate::dfa::{Determinized, Dfa, TooLarge};
+use crate::matcher::{MatchState, Matcher};
+use crate::matches::{Match, Matches};
+use crate::nfa::{Anchor, Next, State, StateVisitor};
+use crate::optimize;
+use crate::options::{MatchKind, Options, RejectsBuilder};
+use crate::parser::{self, ParseError, ParseErrorKind, Warning};
+use crate::replace::{self, Piece, ReplaceError, Replacement};
 use quote::quote;
 use quote::{ToTokens, TokenStreamExt};
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
 
 #[derive(Debug)]
 pub struct Rejects {
-    start: usize,
-    statelist: Vec<State>,
+    pub(crate) start: usize,
+    pub(crate) statelist: Vec<State>,
+    #[cfg(feature = "ast")]
+    ast: Option<Ast>,
+    // used instead of the NFA by `contains`, and to find where matches start for `find_iter`,
+    // when the pattern is a union of literals
+    pub(crate) literals: Option<AhoCorasick>,
+    // every transition is on a set of ASCII characters, see `is_ascii`
+    ascii: bool,
+    prefilter: Option<Prefilter>,
+    pub(crate) match_kind: MatchKind,
+    // see Options::anchored
+    pub(crate) anchored: bool,
+    // see Options::nfc
+    #[cfg(feature = "nfc")]
+    pub(crate) nfc: bool,
+    // see `min_len`, worked out once since every `find_iter` needs it
+    min_len: Option<usize>,
+}
+
+/// The deadline given to `Rejects::is_match_until` passed before the match was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "deadline passed before the match was decided")
+    }
+}
+
+/// More states were active at once than the limit given to `Rejects::find_end_with_limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyActive {
+    pub limit: usize,
+}
+
+impl fmt::Display for TooManyActive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "more than {} states were active at once", self.limit)
+    }
+}
+
+/// Where and why a pattern doesn't match all of some text, see `Rejects::explain_nomatch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoMatchReason {
+    /// The byte offset of the first character the pattern couldn't take, or the length of the
+    /// text if the text ran out before the pattern was matched.
+    pub position: usize,
+    /// The characters which would have let the match carry on at `position`, as named by the
+    /// pattern's literals and classes.
+    pub expected: HashSet<char>,
+    /// Whether characters not in `expected` could also have carried on the match, because of a
+    /// '.' or a negated class like `[^a]` (which doesn't name what it does match).
+    pub any_other: bool,
+    /// Whether the text could have ended at `position`, i.e. the text matched up to there and
+    /// then went on too long.
+    pub end: bool,
+}
+
+// How many characters `is_match_until` reads between looks at the clock.
+const DEADLINE_CHECK_EVERY: usize = 1024;
+
+// A function proposing where matches may start, see `Rejects::with_prefilter`.
+type PrefilterFn = dyn Fn(&str, usize) -> Option<usize> + Send + Sync;
+
+struct Prefilter(Box<PrefilterFn>);
+
+impl fmt::Debug for Prefilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Prefilter")
+    }
 }
 
 impl ToTokens for Rejects {
@@ -22,6 +104,24 @@ impl ToTokens for Rejects {
                 builder.add_state(#state);
             });
         }
+        if let MatchKind::LeftmostFirst = self.match_kind {
+            wrapper_stream.append_all(quote! {
+                builder.match_kind(rejects::options::MatchKind::LeftmostFirst);
+            });
+        }
+        if self.anchored {
+            wrapper_stream.append_all(quote! {
+                builder.anchored(true);
+            });
+        }
+        #[cfg(feature = "nfc")]
+        {
+            if self.nfc {
+                wrapper_stream.append_all(quote! {
+                    builder.nfc(true);
+                });
+            }
+        }
         tokens.append_all(quote! {
             {
                 #wrapper_stream
@@ -33,69 +133,1566 @@ impl ToTokens for Rejects {
 
 #[allow(dead_code)]
 impl Rejects {
-    pub fn new(pat: &str) -> Result<Rejects, Vec<u32>> {
-        let (start, statelist) = parser::parse(pat)?;
-        Ok(Rejects { start, statelist })
+    pub fn new(pat: &str) -> Result<Rejects, Vec<ParseError>> {
+        Ok(Rejects::from_ast(parser::parse_ast(pat)?))
+    }
+
+    /// Same as `Rejects::new` but the pattern has to match all of the text, as if it were written
+    /// `^(pat)$`. `contains`, `find_iter` and the other searches then only find a match which
+    /// spans the whole text.
+    pub fn new_full(pat: &str) -> Result<Rejects, Vec<ParseError>> {
+        let ast = parser::parse_ast(pat)?;
+        let anchor = |anchor| Box::new(Ast::Anchor(anchor));
+        Ok(Rejects::from_ast(Ast::Concat(
+            anchor(Anchor::Start),
+            Box::new(Ast::Concat(
+                Box::new(Ast::Group(Box::new(ast))),
+                anchor(Anchor::End),
+            )),
+        )))
+    }
+
+    /// Same as `Rejects::new` but also returns warnings about the parts of the pattern which are
+    /// valid but probably not what was meant, e.g. the repeated alternative in `a|a`.
+    pub fn with_warnings(pat: &str) -> Result<(Rejects, Vec<Warning>), Vec<ParseError>> {
+        let (ast, warnings) = parser::parse_ast_with_warnings(pat, &Options::default())?;
+        Ok((Rejects::from_ast(ast), warnings))
+    }
+
+    /// Compiles each of `pats` with `Rejects::new`, returning the result for each in the same
+    /// order so an invalid pattern doesn't keep the others from being compiled.
+    pub fn try_compile_all(pats: &[&str]) -> Vec<Result<Rejects, Vec<ParseError>>> {
+        pats.iter().map(|pat| Rejects::new(pat)).collect()
+    }
+
+    /// Same as `Rejects::new` but compiles the pattern according to `options`.
+    pub fn with_options(pat: &str, options: &Options) -> Result<Rejects, Vec<ParseError>> {
+        let ast = parser::parse_ast_with_options(pat, options)?;
+        #[cfg(feature = "nfc")]
+        let ast = if options.nfc { ast.into_nfc() } else { ast };
+        let mut rejects = Rejects::from_ast(ast);
+        if rejects.state_count() > options.max_states.unwrap_or(usize::MAX) {
+            return Err(vec![ParseError {
+                index: 0,
+                kind: ParseErrorKind::TooManyStates,
+            }]);
+        }
+        rejects.match_kind = options.match_kind;
+        rejects.anchored = options.anchored;
+        #[cfg(feature = "nfc")]
+        {
+            rejects.nfc = options.nfc;
+        }
+        Ok(rejects)
+    }
+
+    /// returns a builder for setting the options to compile a pattern with, e.g.
+    /// `Rejects::builder().case_insensitive(true).compile("abc")`.
+    pub fn builder() -> RejectsBuilder {
+        RejectsBuilder::new()
+    }
+
+    /// Wraps an externally built DFA (see `Dfa` for the table format) as a `Rejects`.
+    ///
+    /// Returns the index of the first invalid DFA state if the table is malformed.
+    pub fn from_dfa(dfa: &Dfa) -> Result<Rejects, usize> {
+        dfa.validate()?;
+        let (start, statelist) = dfa.to_states();
+        Ok(Rejects::from(start, statelist))
+    }
+
+    /// Builds a machine which matches exactly `s`.
+    pub fn literal(s: &str) -> Rejects {
+        let ast = s.chars().rev().fold(None, |ast, c| {
+            Ast::concatenation(Some(Ast::Literal(c)), ast)
+        });
+        match ast {
+            Some(ast) => Rejects::from_ast(ast),
+            None => Rejects::from(0, vec![State::make_match()]),
+        }
+    }
+
+    /// returns a machine matching zero or more repetitions of what `self` matches.
+    pub fn star(self) -> Rejects {
+        self.quantify(true, true, Ast::Star)
+    }
+
+    /// returns a machine matching one or more repetitions of what `self` matches.
+    pub fn plus(self) -> Rejects {
+        self.quantify(false, true, Ast::Plus)
+    }
+
+    /// returns a machine matching zero or one repetitions of what `self` matches.
+    pub fn optional(self) -> Rejects {
+        self.quantify(true, false, Ast::Optional)
+    }
+
+    // The same shapes StateList::{kleene, plus, question_mark} build, applied to a finished
+    // machine. A new Split either enters the machine or goes to a new Match, and the old Match
+    // states become Splits leading back to it (`repeat`) or on to the new Match. With `skip` the
+    // new Split becomes the start so the machine can be bypassed. States are only appended, so
+    // existing indices stay valid.
+    fn quantify(mut self, skip: bool, repeat: bool, op: fn(Box<Ast>) -> Ast) -> Rejects {
+        self.wrap_ast(op);
+        // neither prefilter knows where the repeated machine's matches start
+        self.literals = None;
+        self.prefilter = None;
+        let splitter = self.statelist.len();
+        let match_state = splitter + 1;
+        for state in self.statelist.iter_mut() {
+            if let State::Match = state {
+                let out = if repeat { splitter } else { match_state };
+                *state = State::make_split(out, None);
+            }
+        }
+        self.statelist
+            .push(State::make_split(self.start, Some(match_state)));
+        self.statelist.push(State::make_match());
+        if skip {
+            self.start = splitter;
+        }
+        self.min_len = self.shortest_match_len();
+        self
+    }
+
+    #[cfg(feature = "ast")]
+    fn wrap_ast(&mut self, op: fn(Box<Ast>) -> Ast) {
+        self.ast = self
+            .ast
+            .take()
+            .map(|ast| op(Box::new(Ast::Group(Box::new(ast)))));
+    }
+
+    #[cfg(not(feature = "ast"))]
+    fn wrap_ast(&mut self, _op: fn(Box<Ast>) -> Ast) {}
+
+    /// returns the source of a function named `fn_name` which builds this machine, to be written
+    /// to a file (e.g. by a build script) and compiled in place of using `make_rejects!`. The
+    /// function is what the macro would expand to: `pub fn fn_name() -> rejects::rejects::Rejects`.
+    /// The match kind and whether the machine is anchored or normalizes its input (see `Options`)
+    /// are set on the `Builder` it uses, the prefilter (see `with_prefilter`) and the pattern
+    /// given back by `to_regex_string` aren't kept.
+    ///
+    /// # Panics
+    ///
+    /// If `fn_name` isn't a valid identifier.
+    pub fn emit_rust(&self, fn_name: &str) -> String {
+        let name = proc_macro2::Ident::new(fn_name, proc_macro2::Span::call_site());
+        let rejects = self;
+        let source = quote! {
+            pub fn #name() -> rejects::rejects::Rejects {
+                #rejects
+            }
+        };
+        source.to_string()
+    }
+
+    /// returns an expression building a `table::StaticRejects` for this machine. Unlike the
+    /// `ToTokens` output, which adds each state to a `Builder` when it is run, the expression
+    /// can be the value of a `const` or `static` so the machine costs nothing at startup.
+    pub fn to_static_tokens(&self) -> proc_macro2::TokenStream {
+        let start = self.start;
+        let states = self.statelist.iter().map(State::to_static_tokens);
+        quote! {
+            rejects::table::StaticRejects::new(#start, &[#(#states),*])
+        }
+    }
+
+    /// returns a canonical pattern equivalent to the one this machine was compiled from, or
+    /// `None` if it wasn't compiled from a pattern (e.g. it came from `Builder` or `from_dfa`).
+    #[cfg(feature = "ast")]
+    pub fn to_regex_string(&self) -> Option<String> {
+        self.ast.as_ref().map(|ast| ast.to_string())
     }
 
     pub(crate) fn from(start: usize, states: Vec<State>) -> Rejects {
-        Rejects {
+        let mut rejects = Rejects {
             start,
+            ascii: is_ascii(&states),
             statelist: states,
+            #[cfg(feature = "ast")]
+            ast: None,
+            literals: None,
+            prefilter: None,
+            match_kind: MatchKind::LeftmostLongest,
+            anchored: false,
+            #[cfg(feature = "nfc")]
+            nfc: false,
+            min_len: None,
+        };
+        rejects.min_len = rejects.shortest_match_len();
+        rejects
+    }
+
+    /// Compiles a syntax tree, e.g. one from `parse_ast` which has since been changed, the same
+    /// way `Rejects::new` compiles the tree it parses.
+    pub fn from_ast(ast: Ast) -> Rejects {
+        let (start, statelist) = optimize::optimize(ast.clone()).to_nfa();
+        let mut rejects = Rejects {
+            start,
+            ascii: is_ascii(&statelist),
+            statelist,
+            literals: AhoCorasick::from_ast(&ast),
+            prefilter: None,
+            match_kind: MatchKind::LeftmostLongest,
+            anchored: false,
+            #[cfg(feature = "nfc")]
+            nfc: false,
+            min_len: None,
+            #[cfg(feature = "ast")]
+            ast: Some(ast),
+        };
+        rejects.min_len = rejects.shortest_match_len();
+        rejects
+    }
+
+    /// Uses `prefilter` to find where matches may start when searching anywhere in the text (as
+    /// `contains`, `find_iter`, `splitn` and the replace methods do) in place of trying every
+    /// position.
+    ///
+    /// `prefilter(s, from)` returns the byte offset of the first place at or after `from` where a
+    /// match may start, each one is then checked with the machine. When it returns `None` (or an
+    /// offset which isn't on a character boundary of `s` at or after `from`) every position from
+    /// `from` on is tried instead.
+    pub fn with_prefilter<F>(mut self, prefilter: F) -> Rejects
+    where
+        F: Fn(&str, usize) -> Option<usize> + Send + Sync + 'static,
+    {
+        self.prefilter = Some(Prefilter(Box::new(prefilter)));
+        self
+    }
+
+    /// returns the number of states converting this machine to a DFA gives (before minimizing,
+    /// and without the states which can't reach a match), or `TooLarge` as soon as it is known to
+    /// be more than `limit`. A pattern can need a number of DFA states exponential in its length,
+    /// e.g. `(a|b)*a(a|b)(a|b)` remembers the last 3 characters, so this is a cheap way to decide
+    /// whether `minimize` (or matching with a DFA in general) is worth it.
+    ///
+    /// The states of a machine with `^` or `$` in it are counted as if its anchors always held.
+    pub fn nfa_to_dfa_states_estimate(&self, limit: usize) -> Result<usize, TooLarge> {
+        Determinized::from_nfa_with_limit(self.start, &self.statelist, limit)
+            .map(|determinized| determinized.dfa.table.len())
+    }
+
+    /// returns an equivalent machine built from the minimal DFA for the pattern.
+    ///
+    /// The NFA is determinized and then minimized with Hopcroft's algorithm. Like one from
+    /// `from_dfa`, the returned machine has no pattern to give back from `to_regex_string`.
+    /// Whether an anchor holds depends on where in the input the machine is, which a DFA state
+    /// can't know, so a machine with `^` or `$` in it is copied as it is instead.
+    pub fn minimize(&self) -> Rejects {
+        if self.has_anchors() {
+            let statelist = self
+                .statelist
+                .iter()
+                .map(|state| state.shifted(0))
+                .collect();
+            return Rejects::from(self.start, statelist);
         }
+        let (start, statelist) = Determinized::from_nfa(self.start, &self.statelist)
+            .minimize()
+            .to_states();
+        Rejects::from(start, statelist)
+    }
+
+    // true if there is a `^` or `$` anywhere in the machine.
+    fn has_anchors(&self) -> bool {
+        self.statelist
+            .iter()
+            .any(|state| matches!(state, State::Anchor { .. }))
+    }
+
+    /// returns the number of states in the machine.
+    pub fn state_count(&self) -> usize {
+        self.statelist.len()
+    }
+
+    /// returns true if every transition in the machine is on a set of ASCII characters.
+    ///
+    /// `find_end` then runs over the bytes of its input without decoding them: any byte which
+    /// isn't ASCII fails every transition (just as the character it belongs to would), so the
+    /// indices it returns are the same as when matching by character.
+    pub fn is_ascii(&self) -> bool {
+        self.ascii
     }
 
     /// returns index of the end of the match. Uses maximal munch.
+    ///
+    /// The match starts at the beginning of `s` and the index is of its last character, so
+    /// it is -1 both when nothing matches and when only the empty prefix does. The input running
+    /// out part way through the pattern (e.g. `a.` against "a", where the '.' has nothing left to
+    /// match) is no match, and neither is running into a character the pattern can't take: only
+    /// a prefix which the pattern matches in full counts.
     pub fn find_end(&self, s: &str) -> isize {
-        let mut states = HashSet::new();
-        states.insert(self.start);
-        self.epsilon_transition(&mut states, self.start);
+        if self.ascii {
+            self.find_end_iter(s.bytes().map(|b| {
+                if b.is_ascii() {
+                    b as char
+                } else {
+                    std::char::REPLACEMENT_CHARACTER
+                }
+            }))
+        } else {
+            self.find_end_iter(s.chars())
+        }
+    }
+
+    /// Same as `find_end` over a slice of characters, the index is into `chars`.
+    pub fn find_end_chars(&self, chars: &[char]) -> isize {
+        self.find_end_iter(chars.iter().copied())
+    }
+
+    pub(crate) fn find_end_iter<I: Iterator<Item = char>>(&self, chars: I) -> isize {
+        match self.find_end_limited_iter(chars, usize::MAX) {
+            Ok(end) => end,
+            Err(_) => unreachable!("there can't be more than usize::MAX states"),
+        }
+    }
+
+    /// Same as `find_end` but gives up with `TooManyActive` if more than `limit` states are ever
+    /// active at once, to bound the memory a match takes on adversarial input. The states counted
+    /// include the `Split`s which are only passed through, so `a|b` starts with 3 active states.
+    pub fn find_end_with_limit(&self, s: &str, limit: usize) -> Result<isize, TooManyActive> {
+        self.find_end_limited_iter(s.chars(), limit)
+    }
+
+    fn find_end_limited_iter<I: Iterator<Item = char>>(
+        &self,
+        chars: I,
+        limit: usize,
+    ) -> Result<isize, TooManyActive> {
+        let mut states = self.start_states();
+        // the index of the last character of the longest match so far
+        let mut end = -1;
         let mut len = 0;
+        let mut prev = None;
+
+        let mut chars = chars.peekable();
+        let mut i = 0;
+        while let Some(c) = chars.next() {
+            if states.len() > limit {
+                return Err(TooManyActive { limit });
+            }
+            let next = Next::peek(c, &mut chars);
+            // a match of the first i characters, which is only known once the next one is seen
+            if self.is_accepting_before(&states, prev, next) {
+                end = i as isize - 1;
+            }
+            states = self.step(&states, prev, next);
+            if states.is_empty() {
+                return Ok(end);
+            }
+            len = i + 1;
+            i += 1;
+            prev = Some(c);
+        }
+        if states.len() > limit {
+            return Err(TooManyActive { limit });
+        }
+        if self.is_accepting_at_end(&states, prev) {
+            end = len as isize - 1;
+        }
+        Ok(end)
+    }
+
+    /// returns the longest prefix of `s` which the pattern matches, the same match `find_end`
+    /// finds, or `None` if there is none. Unlike with `find_end` an empty match is `Some("")`.
+    pub fn find<'t>(&self, s: &'t str) -> Option<&'t str> {
+        self.longest_match_at(s, 0).map(|end| &s[..end])
+    }
+
+    /// returns the start and end (as character indices, the end being one past the last matched
+    /// character) of the leftmost match in `s`, taking the longest match at that position
+    /// whatever `Options::match_kind` the machine was compiled with. Unlike `find` the match
+    /// doesn't have to start at the beginning of `s`, so `\d+` is found at (3, 6) in "abc123def".
+    ///
+    /// An empty match counts, so `a*` is found at (0, 0) in "bab". Each position is tried in
+    /// turn (only the first with `Options::anchored`), which can take time quadratic in the length
+    /// of `s`.
+    pub fn find_span(&self, s: &str) -> Option<(usize, usize)> {
+        let starts = s
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(s.len()))
+            .enumerate();
+        for (n, start) in starts.take(if self.anchored { 1 } else { usize::MAX }) {
+            if let Some(end) = self.longest_match_at(s, start) {
+                return Some((n, n + s[start..end].chars().count()));
+            }
+        }
+        None
+    }
+
+    /// returns the length (in characters) of every prefix of `s` which the pattern matches, in
+    /// increasing order.
+    pub fn run_to_completion(&self, s: &str) -> Vec<usize> {
+        let mut states = self.start_states();
+        let mut accepting = Vec::new();
+        let mut len = 0;
+        let mut prev = None;
+        for (i, c) in s.char_indices() {
+            let next = Next::of(&s[i..]);
+            if self.is_accepting_before(&states, prev, next) {
+                accepting.push(len);
+            }
+            states = self.step(&states, prev, next);
+            if states.is_empty() {
+                return accepting;
+            }
+            len += 1;
+            prev = Some(c);
+        }
+        if self.is_accepting_at_end(&states, prev) {
+            accepting.push(len);
+        }
+        accepting
+    }
+
+    /// returns true if the pattern matches anywhere in `s`.
+    ///
+    /// `find_end` only considers matches which start at the beginning of `s`, whereas here a match
+    /// may start at any character (or only at the beginning with `Options::anchored`). The search
+    /// stops as soon as any match is found.
+    ///
+    /// Patterns which are only a union of literals (e.g. `foo|bar|baz`) are searched for with an
+    /// Aho–Corasick automaton instead of the NFA, which `find_iter` also uses to find where its
+    /// matches start.
+    ///
+    /// With `Options::nfc` this, `is_match` (and `is_match_chars`, `is_match_os` and
+    /// `is_match_until`), `starts_with_match` and `is_suffix_match` match against
+    /// `s` in NFC. Checking that `s` is already normalized is a pass over it, and when it isn't a
+    /// normalized copy is made first. Searches which report offsets or lengths (e.g. `find_end`,
+    /// `find_iter` and `run_to_completion`) don't normalize `s`, since offsets into a copy
+    /// wouldn't be offsets into `s`, and neither do `resume` and `is_partial_match`, since the end
+    /// of a piece could still combine with what comes after it.
+    pub fn contains(&self, s: &str) -> bool {
+        #[cfg(feature = "nfc")]
+        let s = &*normalize(s, self.nfc);
+        if self.prefilter.is_some() || self.anchored {
+            return self.find_at(s, 0).is_some();
+        }
+        if let Some(literals) = &self.literals {
+            return literals.contains(s);
+        }
+        let mut states = HashSet::new();
+        let mut prev = None;
+        for (i, c) in s.char_indices() {
+            let next = Next::of(&s[i..]);
+            self.add_start(&mut states, prev);
+            if self.is_accepting_before(&states, prev, next) {
+                return true;
+            }
+            states = self.step(&states, prev, next);
+            prev = Some(c);
+        }
+        self.add_start(&mut states, prev);
+        self.is_accepting_at_end(&states, prev)
+    }
+
+    /// returns the state for `resume` to start from, before any input has been fed.
+    pub fn match_state(&self) -> MatchState {
+        let states = self.start_states();
+        MatchState {
+            accepting: self.is_accepting_at_end(&states, None),
+            states,
+            prev: None,
+        }
+    }
+
+    /// Runs the machine over `chunk` starting from `state`, leaving `state` where the machine is
+    /// at the end of it. Feeding the chunks of some input one by one ends up in the same state as
+    /// feeding all of the input at once, so a match may be split across chunks.
+    ///
+    /// The end of each chunk is taken to be the end of the input for `MatchState::is_match`, so
+    /// `a$` is matched after feeding "a" but isn't any more once "b" is fed.
+    pub fn resume(&self, state: &mut MatchState, chunk: &str) {
+        if chunk.is_empty() {
+            return;
+        }
+        let mut before_end = false;
+        for (i, c) in chunk.char_indices() {
+            if state.states.is_empty() {
+                break;
+            }
+            // more input can still come after the chunk, but a `\Z` before a '\n' at the end of
+            // it holds for `is_match`
+            if let Next::FinalNewline = Next::of(&chunk[i..]) {
+                let ended = self.step(&state.states, state.prev, Next::FinalNewline);
+                before_end = self.is_accepting_at_end(&ended, Some(c));
+            }
+            state.states = self.step(&state.states, state.prev, Next::Char(c));
+            state.prev = Some(c);
+        }
+        state.accepting = before_end || self.is_accepting_at_end(&state.states, state.prev);
+    }
+
+    /// returns a cursor which can be fed the input in pieces, starting from the beginning of the
+    /// pattern.
+    pub fn matcher(&self) -> Matcher<'_> {
+        Matcher::new(self)
+    }
+
+    /// returns true if the pattern matches all of `s`, so `a*` matches "" but `a` doesn't match
+    /// "ab". See `starts_with_match` for whether some prefix of `s` is matched instead.
+    ///
+    /// Unlike comparing `find_end` against the length of `s`, this stops reading as soon as no
+    /// state is left.
+    pub fn is_match(&self, s: &str) -> bool {
+        #[cfg(feature = "nfc")]
+        let s = &*normalize(s, self.nfc);
+        self.is_full_match_iter(s.chars())
+    }
+
+    /// Same as `is_match` over a slice of characters, for callers which already have them
+    /// decoded.
+    pub fn is_match_chars(&self, chars: &[char]) -> bool {
+        #[cfg(feature = "nfc")]
+        {
+            if self.nfc {
+                return self.is_match(&chars.iter().collect::<String>());
+            }
+        }
+        self.is_full_match_iter(chars.iter().copied())
+    }
+
+    /// returns true if the pattern matches some prefix of `s`, possibly the empty one. This is
+    /// the match `find_end` looks for, but it stops at the first prefix which is matched.
+    pub fn starts_with_match(&self, s: &str) -> bool {
+        #[cfg(feature = "nfc")]
+        let s = &*normalize(s, self.nfc);
+        self.starts_with_match_at(s, 0)
+    }
+
+    /// Same as `is_match` but gives up with `Timeout` once `deadline` has passed.
+    ///
+    /// The clock is only looked at once every 1024 characters, so a match decided within the first
+    /// 1024 characters (e.g. a mismatch early on) is reported even if the deadline has already
+    /// passed, and the scan can run over the deadline by as long as reading that many characters
+    /// takes.
+    pub fn is_match_until(&self, s: &str, deadline: Instant) -> Result<bool, Timeout> {
+        #[cfg(feature = "nfc")]
+        let s = &*normalize(s, self.nfc);
+        let mut states = self.start_states();
+        let mut prev = None;
+        for (i, (at, c)) in s.char_indices().enumerate() {
+            if (i + 1) % DEADLINE_CHECK_EVERY == 0 && Instant::now() >= deadline {
+                return Err(Timeout);
+            }
+            states = self.step(&states, prev, Next::of(&s[at..]));
+            if states.is_empty() {
+                return Ok(false);
+            }
+            prev = Some(c);
+        }
+        Ok(self.is_accepting_at_end(&states, prev))
+    }
+
+    // Same as `starts_with_match` for a match starting at byte offset `start` of `s`, where a
+    // `^` only holds if `start` is 0.
+    fn starts_with_match_at(&self, s: &str, start: usize) -> bool {
+        let mut states = HashSet::new();
+        let mut prev = s[..start].chars().next_back();
+        self.add_start(&mut states, prev);
+        for (i, c) in s[start..].char_indices() {
+            let next = Next::of(&s[start + i..]);
+            if self.is_accepting_before(&states, prev, next) {
+                return true;
+            }
+            states = self.step(&states, prev, next);
+            if states.is_empty() {
+                return false;
+            }
+            prev = Some(c);
+        }
+        self.is_accepting_at_end(&states, prev)
+    }
+
+    /// returns, for each character of `s`, whether a match (possibly an empty one) starts there.
+    ///
+    /// Every position is tried on its own as in `starts_with_match`, so this can take time
+    /// quadratic in the length of `s`, e.g. for `a*b` over a long run of `a`s.
+    pub fn match_starts(&self, s: &str) -> Vec<bool> {
+        s.char_indices()
+            .map(|(i, _)| self.starts_with_match_at(s, i))
+            .collect()
+    }
+
+    /// returns, for each character of `s` which a match (possibly an empty one) starts at, the
+    /// byte offsets of the longest match starting there. Unlike `find_iter` the matches may
+    /// overlap, so every way the pattern can be found in `s` can be seen.
+    ///
+    /// Every position is matched with maximal munch on its own, whatever `Options::match_kind`
+    /// the machine was compiled with, so this can take time quadratic in the length of `s`.
+    pub fn all_longest_matches(&self, s: &str) -> Vec<(usize, usize)> {
+        s.char_indices()
+            .filter_map(|(i, _)| self.longest_match_at(s, i).map(|end| (i, end)))
+            .collect()
+    }
+
+    /// returns true if the pattern matches all of `s`, kept from before `is_match` matched the
+    /// whole input.
+    #[deprecated(note = "is_match matches all of the input now")]
+    pub fn is_full_match(&self, s: &str) -> bool {
+        self.is_match(s)
+    }
+
+    pub(crate) fn is_full_match_iter<I: Iterator<Item = char>>(&self, chars: I) -> bool {
+        let mut states = self.start_states();
+        let mut prev = None;
+        let mut chars = chars.peekable();
+        while let Some(c) = chars.next() {
+            states = self.step(&states, prev, Next::peek(c, &mut chars));
+            if states.is_empty() {
+                return false;
+            }
+            prev = Some(c);
+        }
+        self.is_accepting_at_end(&states, prev)
+    }
+
+    /// returns true if `s` is the beginning of something the pattern matches (possibly all of
+    /// it), so more input could still lead to a match, e.g. for checking what's been typed so far
+    /// as it is typed. This is whether a `Matcher` fed `s` isn't dead.
+    pub fn is_partial_match(&self, s: &str) -> bool {
+        let mut state = self.match_state();
+        self.resume(&mut state, s);
+        !state.is_dead()
+    }
+
+    /// returns where and why the pattern doesn't match all of `s`, or `None` if it does (see
+    /// `is_match`), e.g. to tell someone "expected a digit at 5" about what they typed.
+    ///
+    /// The machine is run as far into `s` as it can go, and the reason is taken from the states
+    /// it is in there.
+    pub fn explain_nomatch(&self, s: &str) -> Option<NoMatchReason> {
+        let mut states = self.start_states();
+        let mut prev = None;
+        for (i, c) in s.char_indices() {
+            let next = self.step(&states, prev, Next::of(&s[i..]));
+            if next.is_empty() {
+                return Some(self.no_match_reason(i, &states, prev));
+            }
+            states = next;
+            prev = Some(c);
+        }
+        if self.is_accepting_at_end(&states, prev) {
+            None
+        } else {
+            Some(self.no_match_reason(s.len(), &states, prev))
+        }
+    }
+
+    // Why the machine can't go on from `states`, which are at byte offset `position` after the
+    // character `prev`.
+    fn no_match_reason(
+        &self,
+        position: usize,
+        states: &HashSet<usize>,
+        prev: Option<char>,
+    ) -> NoMatchReason {
+        let mut reason = NoMatchReason {
+            position,
+            expected: HashSet::new(),
+            any_other: false,
+            end: self.is_accepting_at_end(states, prev),
+        };
+        for &n in states {
+            if let State::Transition {
+                inclusive,
+                exclusive,
+                out: Some(_),
+            } = &self.statelist[n]
+            {
+                reason.expected.extend(inclusive);
+                reason.any_other |= !exclusive.is_empty() || inclusive.is_empty();
+            }
+        }
+        reason
+    }
+
+    /// returns `Some(s.len())` (the length in bytes) if the pattern matches all of `s`, otherwise
+    /// `None`.
+    pub fn fullmatch_len(&self, s: &str) -> Option<usize> {
+        if self.is_match(s) {
+            Some(s.len())
+        } else {
+            None
+        }
+    }
+
+    /// returns true if the pattern matches all of `s`, for matching file names and paths.
+    ///
+    /// `s` is converted with `OsStr::to_string_lossy`, so anything which isn't valid unicode is
+    /// replaced with U+FFFD first and only matches patterns which accept that character.
+    pub fn is_match_os(&self, s: &OsStr) -> bool {
+        self.is_match(&s.to_string_lossy())
+    }
 
-        for (i, c) in s.chars().enumerate() {
+    /// Splits `s` on matches of the pattern into at most `limit` pieces, the last piece holding the
+    /// unsplit remainder of `s` (like `str::splitn`). Matches are found leftmost first using
+    /// maximal munch, and empty matches don't split.
+    pub fn splitn<'t>(&self, s: &'t str, limit: usize) -> Vec<&'t str> {
+        let mut pieces = Vec::new();
+        if limit == 0 {
+            return pieces;
+        }
+        let mut last = 0;
+        for m in self.find_iter(s).take(limit - 1) {
+            pieces.push(&s[last..m.start()]);
+            last = m.end();
+        }
+        pieces.push(&s[last..]);
+        pieces
+    }
+
+    /// returns an iterator over the matches of the pattern in `s`, leftmost first using maximal
+    /// munch. Empty matches are skipped.
+    ///
+    /// The matches are found as the iterator is advanced. A match can't start within the last
+    /// `min_len() - 1` characters of `s`, so those positions are never tried.
+    pub fn find_iter<'r, 't>(&'r self, s: &'t str) -> Matches<'r, 't> {
+        Matches::new(self, s)
+    }
+
+    /// returns each line of `text` which the pattern matches anywhere in (see `contains`), along
+    /// with its (1 based) line number, as grep would print them.
+    ///
+    /// Lines end at "\n" or "\r\n", which isn't part of the returned line, and the last line
+    /// doesn't need to end with either.
+    pub fn matching_lines<'t>(&self, text: &'t str) -> Vec<(usize, &'t str)> {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| self.contains(line))
+            .map(|(i, line)| (i + 1, line))
+            .collect()
+    }
+
+    /// returns an iterator over the run of matches at the start of `s`, each starting exactly
+    /// where the last one ended, as a tokenizer would match. Iteration stops at the first
+    /// position with no non-empty match instead of searching on past it, as if the machine was
+    /// compiled with `Options::anchored`.
+    pub fn anchored_find_iter<'r, 't>(&'r self, s: &'t str) -> Matches<'r, 't> {
+        Matches::anchored(self, s)
+    }
+
+    /// returns the span of every match `find_iter` finds in `s`, as the indices of the extended
+    /// grapheme clusters of `s` they start and end at instead of byte offsets. Matching is still
+    /// done character by character, so a match may cover only part of a cluster (e.g. a letter
+    /// without its combining accent), such a cluster is counted as part of the match.
+    #[cfg(feature = "graphemes")]
+    pub fn grapheme_spans(&self, s: &str) -> Vec<(usize, usize)> {
+        use unicode_segmentation::UnicodeSegmentation;
+        let starts: Vec<usize> = s.grapheme_indices(true).map(|(i, _)| i).collect();
+        // the index of the cluster holding the byte at `i`
+        let cluster = |i: usize| match starts.binary_search(&i) {
+            Ok(n) => n,
+            Err(n) => n - 1,
+        };
+        self.find_iter(s)
+            .map(|m| (cluster(m.start()), cluster(m.end() - 1) + 1))
+            .collect()
+    }
+
+    /// returns the first match `find_iter` would give, scanning `s` only as far as it has to. The
+    /// iterator works out where the search can stop for each `find_iter` call, which costs more
+    /// than it saves when only one match is wanted.
+    pub fn find_first<'t>(&self, s: &'t str) -> Option<Match<'t>> {
+        let mut pos = 0;
+        loop {
+            let (start, end) = self.find_at(s, pos)?;
+            if start < end {
+                return Some(Match::new(s, start, end));
+            }
+            if self.anchored {
+                return None;
+            }
+            pos = end + s[end..].chars().next()?.len_utf8();
+        }
+    }
+
+    /// returns the number of matches `find_iter` finds in `s`, but stops looking (and returns
+    /// `cap`) once `cap` have been found, so the work done on untrusted input is bounded.
+    pub fn count_capped(&self, s: &str, cap: usize) -> usize {
+        self.find_iter(s).take(cap).count()
+    }
+
+    /// returns the number of non-empty matches in `s`.
+    ///
+    /// Without `overlapping` these are the matches from `find_iter`, each search carrying on from
+    /// the end of the previous match. With it every character where a match starts is counted, so
+    /// `aa` is found 3 times in `aaaa`. That tries a match at each character of `s`, which can
+    /// take time quadratic in the length of `s`.
+    pub fn matches_anywhere_count(&self, s: &str, overlapping: bool) -> usize {
+        if !overlapping {
+            return self.find_iter(s).count();
+        }
+        s.char_indices()
+            .filter(|&(start, _)| matches!(self.match_at(s, start), Some(end) if end > start))
+            .count()
+    }
+
+    /// Replaces every match of the pattern in `s` with `template`, see `try_replace_all`.
+    ///
+    /// # Panics
+    ///
+    /// If `template` is malformed.
+    pub fn replace_all<T: Replacement>(&self, s: &str, template: T) -> String {
+        self.try_replace_all(s, template)
+            .unwrap_or_else(|e| panic!("invalid replacement template: {}", e))
+    }
+
+    /// Replaces every match of the pattern in `s` with `template`, in which `$0` (or `${0}`) is
+    /// the matched text and `$$` is a literal '$'. Matches are found as in `find_iter`, so empty
+    /// matches are left alone. Wrap the replacement in `NoExpand` to use it as it is instead.
+    ///
+    /// Returns the position of the first error in `template` if it is malformed.
+    pub fn try_replace_all<T: Replacement>(
+        &self,
+        s: &str,
+        template: T,
+    ) -> Result<String, ReplaceError> {
+        let pieces = replace::parse_template(&template.to_template())?;
+        Ok(self.replace_all_with(s, |m| {
+            pieces
+                .iter()
+                .map(|piece| match piece {
+                    Piece::Literal(literal) => literal,
+                    Piece::Match => m.as_str(),
+                })
+                .collect()
+        }))
+    }
+
+    /// Reads text from `r` and writes it to `w` with every match replaced by `template` (see
+    /// `try_replace_all`), without reading all of `r` into memory.
+    ///
+    /// The text is searched in a sliding window. A match starting at some character can only be
+    /// replaced once `max_len()` characters after it have been read, since until then more input
+    /// could still make the match longer or make one where there was none, so about `max_len()`
+    /// characters plus the buffer of `r` are held in memory at a time. When `max_len()` is `None`
+    /// nothing is certain until the end of the input, and all of it is read in first. The same
+    /// goes for a pattern with `^` or `$` in it, since these only hold at the ends of all of the
+    /// input and not of the window.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if `template` is malformed, and with
+    /// `ErrorKind::InvalidData` if the input isn't valid UTF-8.
+    pub fn replace_stream<R: BufRead, W: Write, T: Replacement>(
+        &self,
+        mut r: R,
+        mut w: W,
+        template: T,
+    ) -> io::Result<()> {
+        let pieces = replace::parse_template(&template.to_template())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let window = if self.has_anchors() {
+            None
+        } else {
+            self.max_len()
+        };
+        let mut text = String::new();
+        // the end of the input read so far may be part way through a character
+        let mut partial = Vec::new();
+        // text[..written] has been written out, the search carries on from pos
+        let mut written = 0;
+        let mut pos = 0;
+        loop {
+            let chunk = r.fill_buf()?;
+            let eof = chunk.is_empty();
+            partial.extend_from_slice(chunk);
+            let read = chunk.len();
+            r.consume(read);
+            let valid = match std::str::from_utf8(&partial) {
+                Ok(_) => partial.len(),
+                Err(e) if e.error_len().is_none() && !eof => e.valid_up_to(),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+            text.push_str(std::str::from_utf8(&partial[..valid]).unwrap());
+            partial.drain(..valid);
+
+            // matches which start before limit can't reach past the end of the text
+            let limit = match window {
+                _ if eof => text.len(),
+                Some(0) => text.len(),
+                Some(n) => text.char_indices().rev().nth(n - 1).map_or(0, |(i, _)| i),
+                None => 0,
+            };
+            while let Some((start, end)) = self.find_at(&text, pos) {
+                if start >= limit {
+                    break;
+                }
+                if start == end {
+                    match text[end..].chars().next() {
+                        Some(c) => pos = end + c.len_utf8(),
+                        None => break,
+                    }
+                    continue;
+                }
+                w.write_all(&text.as_bytes()[written..start])?;
+                for piece in &pieces {
+                    match piece {
+                        Piece::Literal(literal) => w.write_all(literal.as_bytes())?,
+                        Piece::Match => w.write_all(&text.as_bytes()[start..end])?,
+                    }
+                }
+                written = end;
+                pos = end;
+            }
+
+            // nothing before limit is part of a match that hasn't been replaced yet
+            let flush = written.max(limit);
+            w.write_all(&text.as_bytes()[written..flush])?;
+            text.drain(..flush);
+            pos = pos.max(flush) - flush;
+            written = 0;
+            if eof {
+                return w.flush();
+            }
+        }
+    }
+
+    /// Replaces every match of the pattern in `s` (those from `find_iter`) with what `f` returns
+    /// for it.
+    pub fn replace_all_with<F: FnMut(&Match) -> String>(&self, s: &str, mut f: F) -> String {
+        let mut replaced = String::with_capacity(s.len());
+        let mut last = 0;
+        for m in self.find_iter(s) {
+            replaced.push_str(&s[last..m.start()]);
+            replaced.push_str(&f(&m));
+            last = m.end();
+        }
+        replaced.push_str(&s[last..]);
+        replaced
+    }
+
+    /// returns true if some suffix of `s` (possibly the empty suffix) is matched in its entirety.
+    ///
+    /// Rather than trying every start position, this runs the machine in reverse: starting from the
+    /// `Match` states it follows the edges backwards over the characters of `s` from the end, and
+    /// succeeds once `start` is reached.
+    pub fn is_suffix_match(&self, s: &str) -> bool {
+        #[cfg(feature = "nfc")]
+        let s = &*normalize(s, self.nfc);
+        let (epsilon_preds, character_preds) = self.predecessors();
+        let mut states = HashSet::new();
+        for (n, state) in self.statelist.iter().enumerate() {
+            if let State::Match = state {
+                let at = (s.chars().next_back(), Next::End);
+                self.reverse_epsilon_transition(&epsilon_preds, &mut states, n, at);
+            }
+        }
+
+        for (i, c) in s.char_indices().rev() {
+            if states.contains(&self.start) {
+                return true;
+            }
             let mut newstates = HashSet::new();
             for &state in states.iter() {
-                self.character_transition(&mut newstates, state, c);
+                for &pred in character_preds[state].iter() {
+                    if self.statelist[pred].transition(c).is_some() {
+                        let at = (s[..i].chars().next_back(), Next::of(&s[i..]));
+                        self.reverse_epsilon_transition(&epsilon_preds, &mut newstates, pred, at);
+                    }
+                }
             }
-            if newstates.len() == 0 {
-                return (i as isize) - 1;
-            } else {
-                states = newstates;
+            if newstates.is_empty() {
+                return false;
             }
-            len += 1;
+            states = newstates;
         }
-        let accept = states.into_iter().any(|n| {
-            if let State::Match = &self.statelist[n] {
-                true
-            } else {
-                false
+        states.contains(&self.start)
+    }
+
+    /// Panics (in debug builds) if the machine is malformed: `start` or any `out`/`out1`/`out2`
+    /// is not a valid state index, or there is no `Match` state reachable from `start`.
+    pub fn debug_assert_valid(&self) {
+        let len = self.statelist.len();
+        debug_assert!(
+            self.start < len,
+            "start state {} is out of range ({} states)",
+            self.start,
+            len
+        );
+        for (i, state) in self.statelist.iter().enumerate() {
+            for out in state.outs() {
+                debug_assert!(
+                    out < len,
+                    "state {} points to state {} which is out of range ({} states)",
+                    i,
+                    out,
+                    len
+                );
             }
-        });
-        if accept {
-            len - 1
+        }
+        debug_assert!(
+            !self.is_empty_language(),
+            "no Match state is reachable from start state {}",
+            self.start
+        );
+    }
+
+    /// returns true if the machine matches nothing, not even the empty string, because there is
+    /// no path from `start` to a `Match` state.
+    pub fn is_empty_language(&self) -> bool {
+        !self
+            .reachable()
+            .into_iter()
+            .any(|n| matches!(self.statelist[n], State::Match))
+    }
+
+    /// returns false if no input of exactly `n` characters can be a full match, checked against
+    /// `min_len` and `max_len` without running the machine. A true answer only means `n` is in
+    /// range, e.g. `(aa)+` can't match 3 characters but this is true for any `n >= 2`.
+    ///
+    /// Both bounds are worked out on each call, which takes time in the size of the machine and
+    /// not the input, so when validating many inputs it can be cheaper to call `min_len` and
+    /// `max_len` once.
+    pub fn could_match_len(&self, n: usize) -> bool {
+        match self.min_len() {
+            Some(min) => min <= n && n <= self.max_len().unwrap_or(usize::MAX),
+            None => false,
+        }
+    }
+
+    /// returns the fewest characters a match can be made of, or `None` if the machine matches
+    /// nothing.
+    pub fn min_len(&self) -> Option<usize> {
+        self.min_len
+    }
+
+    fn shortest_match_len(&self) -> Option<usize> {
+        // breadth first where epsilon edges cost nothing, so they go on the front of the queue
+        let mut dist = vec![None; self.statelist.len()];
+        let mut queue = VecDeque::new();
+        queue.push_back((self.start, 0));
+        while let Some((n, d)) = queue.pop_front() {
+            if n >= self.statelist.len() || dist[n].is_some() {
+                continue;
+            }
+            if let State::Match = self.statelist[n] {
+                return Some(d);
+            }
+            dist[n] = Some(d);
+            for (out, w) in self.edges(n) {
+                if w == 0 {
+                    queue.push_front((out, d));
+                } else {
+                    queue.push_back((out, d + w));
+                }
+            }
+        }
+        None
+    }
+
+    /// returns the most characters a match can be made of, or `None` if there is no limit (some
+    /// `*` or `+` can repeat on the way to a match). A machine which matches nothing gives
+    /// `Some(0)`.
+    pub fn max_len(&self) -> Option<usize> {
+        let len = self.statelist.len();
+        let mut live = vec![false; len];
+        for n in self.reachable() {
+            live[n] = true;
+        }
+        let mut preds = vec![Vec::new(); len];
+        for n in (0..len).filter(|&n| live[n]) {
+            for (out, _) in self.edges(n) {
+                preds[out].push(n);
+            }
+        }
+        // only keep the states which can also get to a Match
+        let mut stack: Vec<usize> = (0..len)
+            .filter(|&n| live[n] && matches!(self.statelist[n], State::Match))
+            .collect();
+        let mut matching = vec![false; len];
+        while let Some(n) = stack.pop() {
+            if !matching[n] {
+                matching[n] = true;
+                stack.extend(preds[n].iter().copied());
+            }
+        }
+        if self.start >= len || !matching[self.start] {
+            return Some(0);
+        }
+
+        // Kosaraju's algorithm, the components are found in topological order
+        let mut order = Vec::new();
+        let mut visited = vec![false; len];
+        let mut stack = vec![(self.start, 0)];
+        visited[self.start] = true;
+        while let Some((n, i)) = stack.pop() {
+            let edges = self.edges(n);
+            match edges.get(i) {
+                Some(&(out, _)) => {
+                    stack.push((n, i + 1));
+                    if matching[out] && !visited[out] {
+                        visited[out] = true;
+                        stack.push((out, 0));
+                    }
+                }
+                None => order.push(n),
+            }
+        }
+        let mut component = vec![None; len];
+        let mut components = 0;
+        for &root in order.iter().rev() {
+            if component[root].is_some() {
+                continue;
+            }
+            let mut stack = vec![root];
+            while let Some(n) = stack.pop() {
+                if component[n].is_none() {
+                    component[n] = Some(components);
+                    stack.extend(
+                        preds[n]
+                            .iter()
+                            .filter(|&&p| matching[p] && component[p].is_none()),
+                    );
+                }
+            }
+            components += 1;
+        }
+
+        // a component is only free to loop if it doesn't read a character while doing so
+        let mut longest = vec![0; components];
+        let mut members = vec![Vec::new(); components];
+        for &n in &order {
+            members[component[n].unwrap()].push(n);
+        }
+        for c in (0..components).rev() {
+            for &n in &members[c] {
+                for (out, w) in self.edges(n) {
+                    match component.get(out).copied().flatten() {
+                        Some(d) if d == c && w > 0 => return None,
+                        Some(d) if d != c && matching[out] => {
+                            longest[c] = longest[c].max(longest[d] + w)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Some(longest[component[self.start].unwrap()])
+    }
+
+    /// returns the literal every match starts with, e.g. "http://" for `http://\w+`, or `None` if
+    /// a match can start with more than one character.
+    ///
+    /// This follows the transitions from the start state which can only take one character
+    /// (passing over any anchors), stopping at the first `Split`, class or `Match`.
+    pub fn literal_prefix(&self) -> Option<String> {
+        let mut prefix = String::new();
+        let mut n = self.start;
+        // a hand built machine can loop back on itself without a Split
+        for _ in 0..self.statelist.len() {
+            match &self.statelist[n] {
+                State::Transition {
+                    inclusive,
+                    exclusive,
+                    out: Some(out),
+                } if inclusive.len() == 1 && exclusive.is_empty() => {
+                    prefix.extend(inclusive.iter());
+                    n = *out;
+                }
+                State::Anchor { out: Some(out), .. } => n = *out,
+                _ => break,
+            }
+        }
+        if prefix.is_empty() {
+            None
         } else {
-            -1
+            Some(prefix)
         }
     }
 
+    /// Calls `v` for each state, either those reachable from `start` (depth first, following
+    /// `out1` before `out2`) when `start_from_root` is set, or else all of them in index order.
+    pub fn walk<V: StateVisitor>(&self, start_from_root: bool, v: &mut V) {
+        let order = if start_from_root {
+            self.reachable()
+        } else {
+            (0..self.statelist.len()).collect()
+        };
+        for n in order {
+            self.statelist[n].accept(n, v);
+        }
+    }
+
+    /// returns every state that can be reached from `start` in depth first order, ignoring out of
+    /// range indices.
+    fn reachable(&self) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        let mut order = Vec::new();
+        let mut stack = vec![self.start];
+        while let Some(n) = stack.pop() {
+            if n >= self.statelist.len() || !seen.insert(n) {
+                continue;
+            }
+            order.push(n);
+            stack.extend(self.statelist[n].outs().into_iter().rev());
+        }
+        order
+    }
+
+    // The states `n` has an edge to, with the number of characters read along each edge.
+    fn edges(&self, n: usize) -> Vec<(usize, usize)> {
+        let w = match self.statelist[n] {
+            State::Transition { .. } => 1,
+            _ => 0,
+        };
+        self.statelist[n]
+            .outs()
+            .into_iter()
+            .filter(|&out| out < self.statelist.len())
+            .map(|out| (out, w))
+            .collect()
+    }
+
+    /// returns, for each state, the states with an epsilon edge into it and the states with a
+    /// character edge into it.
+    fn predecessors(&self) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        let mut epsilon_preds = vec![Vec::new(); self.statelist.len()];
+        let mut character_preds = vec![Vec::new(); self.statelist.len()];
+        for (n, state) in self.statelist.iter().enumerate() {
+            match state {
+                State::Transition { out: Some(out), .. } => character_preds[*out].push(n),
+                State::Split { .. } | State::Anchor { .. } => {
+                    for out in state.outs() {
+                        epsilon_preds[out].push(n);
+                    }
+                }
+                _ => {}
+            }
+        }
+        (epsilon_preds, character_preds)
+    }
+
+    // Adds `state` and everything that reaches it on epsilon edges to `newstates`, going back
+    // through the anchors which hold where `at` says `state` is (between the character before it
+    // and what comes after it).
+    fn reverse_epsilon_transition(
+        &self,
+        epsilon_preds: &[Vec<usize>],
+        newstates: &mut HashSet<usize>,
+        state: usize,
+        (prev, next): (Option<char>, Next),
+    ) {
+        let mut stack = vec![state];
+        while let Some(n) = stack.pop() {
+            if newstates.insert(n) {
+                stack.extend(
+                    epsilon_preds[n]
+                        .iter()
+                        .copied()
+                        .filter(|&pred| match self.statelist[pred] {
+                            State::Anchor { anchor, .. } => anchor.holds(prev, next),
+                            _ => true,
+                        }),
+                );
+            }
+        }
+    }
+
+    /// returns the byte offsets of the leftmost match at or after byte offset `from`, choosing the
+    /// match at that position according to the `MatchKind`.
+    pub(crate) fn find_at(&self, s: &str, from: usize) -> Option<(usize, usize)> {
+        self.find_in(s, from, s.len())
+    }
+
+    // Same as `find_at` but only tries the starts up to byte offset `last_start`, for when no
+    // match can start after it.
+    pub(crate) fn find_in(
+        &self,
+        s: &str,
+        mut from: usize,
+        last_start: usize,
+    ) -> Option<(usize, usize)> {
+        if from > last_start {
+            return None;
+        }
+        if self.anchored {
+            return self.match_at(s, from).map(|end| (from, end));
+        }
+        if let Some(Prefilter(prefilter)) = &self.prefilter {
+            while let Some(start) = prefilter(s, from) {
+                if start < from || !s.is_char_boundary(start) {
+                    break;
+                }
+                if start > last_start {
+                    return None;
+                }
+                if let Some(end) = self.match_at(s, start) {
+                    return Some((start, end));
+                }
+                from = start + s[start..].chars().next()?.len_utf8();
+            }
+        }
+        if let Some(literals) = &self.literals {
+            // every match is one of the literals, so it can only start where one of them occurs
+            while let Some(start) = literals.find_start(s, from) {
+                if start > last_start {
+                    return None;
+                }
+                if let Some(end) = self.match_at(s, start) {
+                    return Some((start, end));
+                }
+                from = start + s[start..].chars().next()?.len_utf8();
+            }
+            return None;
+        }
+        let starts = s[from..].char_indices().map(|(i, _)| from + i);
+        for start in starts.chain(std::iter::once(s.len())) {
+            if start > last_start {
+                break;
+            }
+            if let Some(end) = self.match_at(s, start) {
+                return Some((start, end));
+            }
+        }
+        None
+    }
+
+    pub(crate) fn match_at(&self, s: &str, start: usize) -> Option<usize> {
+        match self.match_kind {
+            MatchKind::LeftmostLongest => self.longest_match_at(s, start),
+            MatchKind::LeftmostFirst => self.first_match_at(s, start),
+        }
+    }
+
+    /// returns the byte offset just past the longest match starting at byte offset `start`.
+    fn longest_match_at(&self, s: &str, start: usize) -> Option<usize> {
+        let mut states = HashSet::new();
+        let mut prev = s[..start].chars().next_back();
+        self.add_start(&mut states, prev);
+        let mut end = None;
+        for (i, c) in s[start..].char_indices() {
+            let next = Next::of(&s[start + i..]);
+            if self.is_accepting_before(&states, prev, next) {
+                end = Some(start + i);
+            }
+            states = self.step(&states, prev, next);
+            if states.is_empty() {
+                return end;
+            }
+            prev = Some(c);
+        }
+        if self.is_accepting_at_end(&states, prev) {
+            end = Some(s.len());
+        }
+        end
+    }
+
+    /// returns the byte offset just past the highest priority match starting at byte offset
+    /// `start`, where `out1` of a `Split` is preferred over `out2`.
+    ///
+    /// This is a Pike VM: the threads are kept in priority order, and once one of them reaches a
+    /// `Match` the threads after it are dropped since any match they find loses to that one.
+    fn first_match_at(&self, s: &str, start: usize) -> Option<usize> {
+        let mut threads = Vec::new();
+        let at = (s[..start].chars().next_back(), Next::of(&s[start..]));
+        self.add_thread(&mut threads, &mut HashSet::new(), self.start, at);
+        let mut end = None;
+        let mut chars = s[start..].char_indices();
+        while !threads.is_empty() {
+            let next = chars.next();
+            let mut next_threads = Vec::new();
+            let mut seen = HashSet::new();
+            for &n in &threads {
+                if let State::Match = self.statelist[n] {
+                    end = Some(start + next.map_or(s.len() - start, |(i, _)| i));
+                    break;
+                }
+                if let Some((i, c)) = next {
+                    if let Some(out) = self.statelist[n].transition(c) {
+                        let at = (Some(c), Next::of(&s[start + i + c.len_utf8()..]));
+                        self.add_thread(&mut next_threads, &mut seen, out, at);
+                    }
+                }
+            }
+            if next.is_none() {
+                break;
+            }
+            threads = next_threads;
+        }
+        end
+    }
+
+    // Adds `n` and everything it reaches on epsilon edges to `threads`, out1 before out2. `at`
+    // is the character before the threads (`None` at the start of the input) and what comes
+    // after them, for the anchors.
+    fn add_thread(
+        &self,
+        threads: &mut Vec<usize>,
+        seen: &mut HashSet<usize>,
+        n: usize,
+        (prev, next): (Option<char>, Next),
+    ) {
+        let mut stack = vec![n];
+        while let Some(n) = stack.pop() {
+            if !seen.insert(n) {
+                continue;
+            }
+            match self.statelist[n] {
+                State::Split { out1, out2 } => {
+                    stack.extend(out2);
+                    stack.push(out1);
+                }
+                State::Anchor { anchor, out } => {
+                    if anchor.holds(prev, next) {
+                        stack.extend(out);
+                    }
+                }
+                _ => threads.push(n),
+            }
+        }
+    }
+
+    /// returns the states the machine starts in at the beginning of the input.
+    pub(crate) fn start_states(&self) -> HashSet<usize> {
+        let mut states = HashSet::new();
+        self.add_start(&mut states, None);
+        states
+    }
+
+    // Adds the states a match starting here begins in, `prev` being the character before here
+    // (`None` at the beginning of the input).
+    fn add_start(&self, states: &mut HashSet<usize>, prev: Option<char>) {
+        self.epsilon_transition(states, self.start, prev, None);
+    }
+
+    /// returns the states reached from `states` on the character `next`, including their
+    /// epsilon closure. `prev` is the character before it (`None` at the start of the input), for
+    /// the `$`s in `states` which were waiting to see `next`. There is no character to read at
+    /// `Next::End`, so no state is reached.
+    pub(crate) fn step(
+        &self,
+        states: &HashSet<usize>,
+        prev: Option<char>,
+        next: Next,
+    ) -> HashSet<usize> {
+        let mut newstates = HashSet::new();
+        if let Some(symbol) = next.char() {
+            for &state in self.states_before(states, prev, next).iter() {
+                self.character_transition(&mut newstates, state, symbol);
+            }
+        }
+        newstates
+    }
+
+    /// returns true if there is a match where the machine is in `states`, without taking a `$`
+    /// which is waiting for the character after it. See `is_accepting_before` for when that
+    /// character is known.
+    pub(crate) fn is_accepting(&self, states: &HashSet<usize>) -> bool {
+        states
+            .iter()
+            .any(|&n| matches!(self.statelist[n], State::Match))
+    }
+
+    /// Same as `is_accepting` where `states` are between `prev` and `next`, so the `$`s in them
+    /// which hold there are taken.
+    pub(crate) fn is_accepting_before(
+        &self,
+        states: &HashSet<usize>,
+        prev: Option<char>,
+        next: Next,
+    ) -> bool {
+        self.is_accepting(states) || self.is_accepting(&self.states_before(states, prev, next))
+    }
+
+    /// Same as `is_accepting` where `states` are at the end of the input, after `prev` (`None`
+    /// if the input is empty).
+    pub(crate) fn is_accepting_at_end(&self, states: &HashSet<usize>, prev: Option<char>) -> bool {
+        self.is_accepting_before(states, prev, Next::End)
+    }
+
+    /// returns `states` along with everything their `$`s lead to where the states are between
+    /// `prev` (`None` at the start of the input) and `next`.
+    pub(crate) fn states_before<'s>(
+        &self,
+        states: &'s HashSet<usize>,
+        prev: Option<char>,
+        next: Next,
+    ) -> Cow<'s, HashSet<usize>> {
+        let mut passed = Cow::Borrowed(states);
+        for &n in states {
+            if let State::Anchor {
+                anchor,
+                out: Some(out),
+            } = self.statelist[n]
+            {
+                if anchor.looks_ahead() && anchor.holds(prev, next) {
+                    self.epsilon_transition(passed.to_mut(), out, prev, Some(next));
+                }
+            }
+        }
+        passed
+    }
+
     fn character_transition(&self, newstates: &mut HashSet<usize>, state: usize, symbol: char) {
-        if let Some(out) = &self.statelist[state].transition(symbol) {
-            newstates.insert(*out);
-            self.epsilon_transition(newstates, *out);
+        if let Some(out) = self.statelist[state].transition(symbol) {
+            self.epsilon_transition(newstates, out, Some(symbol), None);
         }
     }
 
-    fn epsilon_transition(&self, newstates: &mut HashSet<usize>, state: usize) {
-        match &self.statelist[state] {
-            State::Split { out1, out2 } => {
-                newstates.insert(*out1);
-                self.epsilon_transition(newstates, *out1);
-                if let Some(out) = *out2 {
-                    newstates.insert(out);
-                    self.epsilon_transition(newstates, out);
+    // Adds `state` and everything it reaches on epsilon edges to `newstates`, going through the
+    // anchors which hold between `prev` and `next`, what is before and after the states.
+    // A state already in `newstates` isn't followed again, so epsilon cycles (e.g. in `(a?b?)*`)
+    // end.
+    //
+    // A `^` which doesn't hold is left out since it never will further on, but while `next` is
+    // `None` a `$` is kept for `states_before`: the character after it (or the end of the input)
+    // isn't known until it is read.
+    fn epsilon_transition(
+        &self,
+        newstates: &mut HashSet<usize>,
+        state: usize,
+        prev: Option<char>,
+        next: Option<Next>,
+    ) {
+        let mut stack = vec![state];
+        while let Some(n) = stack.pop() {
+            if let State::Anchor { anchor, .. } = self.statelist[n] {
+                if !anchor.looks_ahead() && !anchor.holds(prev, Next::End) {
+                    continue;
+                }
+            }
+            if !newstates.insert(n) {
+                continue;
+            }
+            // Match and Nil and Transition don't have epsilon transitions
+            match self.statelist[n] {
+                State::Split { out1, out2 } => {
+                    stack.extend(out2);
+                    stack.push(out1);
+                }
+                State::Anchor { anchor, out }
+                    if !anchor.looks_ahead()
+                        || matches!(next, Some(next) if anchor.holds(prev, next)) =>
+                {
+                    stack.extend(out)
                 }
+                _ => {}
             }
-            _ => {} // Match and Nil and Transition don't have epsilon transitions
         }
     }
 }
+
+fn is_ascii(states: &[State]) -> bool {
+    states.iter().all(|state| match state {
+        State::Transition {
+            inclusive,
+            exclusive,
+            ..
+        } => !inclusive.is_empty() && exclusive.is_empty() && inclusive.iter().all(char::is_ascii),
+        _ => true,
+    })
+}
+
+// `s` in NFC if `nfc` is set, see `Options::nfc`.
+#[cfg(feature = "nfc")]
+pub(crate) fn normalize(s: &str, nfc: bool) -> std::borrow::Cow<'_, str> {
+    use unicode_normalization::UnicodeNormalization;
+    if nfc && !unicode_normalization::is_nfc(s) {
+        std::borrow::Cow::Owned(s.nfc().collect())
+    } else {
+        std::borrow::Cow::Borrowed(s)
+    }
+}
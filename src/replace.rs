@@ -0,0 +1,135 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// An error found in a replacement template passed to `Rejects::try_replace_all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplaceError {
+    /// The index of the character in the template where the error was found.
+    pub index: u32,
+    pub kind: ReplaceErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceErrorKind {
+    /// A '$' at the end of the template.
+    TrailingDollar,
+    /// A "${" without a closing '}'.
+    UnterminatedBrace,
+    /// A '$' which isn't followed by a group number, '{' or another '$'.
+    InvalidGroup,
+    /// A group number other than 0, patterns have no capture groups.
+    UnknownGroup,
+}
+
+impl fmt::Display for ReplaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ReplaceErrorKind::TrailingDollar => write!(f, "trailing '$' at {}", self.index),
+            ReplaceErrorKind::UnterminatedBrace => {
+                write!(f, "unterminated '${{' at {}", self.index)
+            }
+            ReplaceErrorKind::InvalidGroup => write!(f, "invalid group at {}", self.index),
+            ReplaceErrorKind::UnknownGroup => write!(f, "unknown group at {}", self.index),
+        }
+    }
+}
+
+/// A replacement used as it is, so a '$' in it is just a '$' instead of starting a reference to
+/// the match. Passed in place of a template, e.g. `rejects.replace_all(s, NoExpand("$1"))`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoExpand<'a>(pub &'a str);
+
+/// What matches can be replaced with by `Rejects::replace_all` and friends, either a template or
+/// a `NoExpand` literal.
+pub trait Replacement {
+    /// returns the template this replacement is the same as.
+    fn to_template(&self) -> Cow<'_, str>;
+}
+
+impl Replacement for &str {
+    fn to_template(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Replacement for &String {
+    fn to_template(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.as_str())
+    }
+}
+
+impl Replacement for NoExpand<'_> {
+    fn to_template(&self) -> Cow<'_, str> {
+        Cow::Owned(self.0.replace('$', "$$"))
+    }
+}
+
+/// A piece of a parsed replacement template.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Piece {
+    Literal(String),
+    /// `$0` or `${0}`, the whole match.
+    Match,
+}
+
+/// Parses a replacement template, in which `$0` and `${0}` stand for the match and `$$` for a
+/// literal '$'.
+pub(crate) fn parse_template(template: &str) -> Result<Vec<Piece>, ReplaceError> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut iter = template.chars().enumerate().peekable();
+    while let Some((i, c)) = iter.next() {
+        if c != '$' {
+            literal.push(c);
+            continue;
+        }
+        let error = |kind| ReplaceError {
+            index: i as u32,
+            kind,
+        };
+        let group = match iter.next() {
+            None => return Err(error(ReplaceErrorKind::TrailingDollar)),
+            Some((_, '$')) => {
+                literal.push('$');
+                continue;
+            }
+            Some((_, '{')) => {
+                let mut group = String::new();
+                loop {
+                    match iter.next() {
+                        None => return Err(error(ReplaceErrorKind::UnterminatedBrace)),
+                        Some((_, '}')) => break,
+                        Some((_, c)) => group.push(c),
+                    }
+                }
+                group
+            }
+            Some((_, c)) if c.is_ascii_digit() => {
+                let mut group = c.to_string();
+                while let Some(&(_, c)) = iter.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    group.push(c);
+                    iter.next();
+                }
+                group
+            }
+            Some(_) => return Err(error(ReplaceErrorKind::InvalidGroup)),
+        };
+        match group.parse::<usize>() {
+            Ok(0) => {
+                if !literal.is_empty() {
+                    pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                }
+                pieces.push(Piece::Match);
+            }
+            Ok(_) => return Err(error(ReplaceErrorKind::UnknownGroup)),
+            Err(_) => return Err(error(ReplaceErrorKind::InvalidGroup)),
+        }
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
@@ -0,0 +1,247 @@
+use crate::ast::Ast;
+use crate::nfa::{Next, State, StateList};
+use crate::optimize;
+use crate::parser::{self, ParseError};
+use crate::rejects::Rejects;
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+
+/// A list of patterns which are matched against the same text together, e.g. a blocklist.
+#[derive(Debug)]
+pub struct RejectsSet {
+    patterns: Vec<Rejects>,
+    // every pattern's states one after another behind a chain of Splits, see `longest_match`
+    union: Rejects,
+    // the index of the pattern each of `union`'s Match states came from
+    match_ids: HashMap<usize, usize>,
+}
+
+impl RejectsSet {
+    /// Compiles each of `patterns`, returning every error found along with the index of the
+    /// pattern it is in.
+    pub fn new(patterns: &[&str]) -> Result<RejectsSet, Vec<(usize, ParseError)>> {
+        RejectsSet::compile(patterns.iter().copied().enumerate())
+    }
+
+    /// Same as `RejectsSet::new`, but the machine `longest_match` runs shares the states for the
+    /// beginnings the patterns have in common, e.g. `http://a\.com` and `http://b\.org` share the
+    /// states for `http://`, so a list of similar patterns takes far fewer states (see
+    /// `state_count`).
+    ///
+    /// Only whole pieces of a pattern's concatenation are shared: `(ab)c` and `abd` share
+    /// nothing since the group is one piece.
+    pub fn compile_trie(patterns: &[&str]) -> Result<RejectsSet, Vec<(usize, ParseError)>> {
+        let mut set = RejectsSet::new(patterns)?;
+        let mut root = TrieNode::default();
+        for (id, pat) in patterns.iter().enumerate() {
+            let ast = parser::parse_ast(pat).expect("the pattern was already compiled");
+            let mut atoms = Vec::new();
+            atoms_of(optimize::optimize(ast), &mut atoms);
+            root.insert(atoms, id);
+        }
+        if !patterns.is_empty() {
+            let mut statelist = StateList::new();
+            set.match_ids.clear();
+            let start = root.compile(&mut statelist, &mut set.match_ids);
+            set.union = Rejects::from(start, statelist.states);
+        }
+        Ok(set)
+    }
+
+    /// Compiles each non-empty line of `r` as a pattern, returning every error found along with
+    /// the (1 based) number of the line it is on.
+    ///
+    /// Reading stops at the first line which can't be read, e.g. one which isn't valid UTF-8.
+    pub fn from_lines<R: BufRead>(r: R) -> Result<RejectsSet, Vec<(usize, ParseError)>> {
+        let lines: Vec<(usize, String)> = r
+            .lines()
+            .take_while(|line| line.is_ok())
+            .flatten()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line))
+            .filter(|(_, line)| !line.is_empty())
+            .collect();
+        RejectsSet::compile(lines.iter().map(|(n, line)| (*n, line.as_str())))
+    }
+
+    fn compile<'a, I: Iterator<Item = (usize, &'a str)>>(
+        patterns: I,
+    ) -> Result<RejectsSet, Vec<(usize, ParseError)>> {
+        let mut compiled = Vec::new();
+        let mut errors = Vec::new();
+        for (n, pat) in patterns {
+            match Rejects::new(pat) {
+                Ok(rejects) => compiled.push(rejects),
+                Err(errs) => errors.extend(errs.into_iter().map(|e| (n, e))),
+            }
+        }
+        if errors.is_empty() {
+            let (union, match_ids) = union(&compiled);
+            Ok(RejectsSet {
+                patterns: compiled,
+                union,
+                match_ids,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// returns the indices of the patterns which match anywhere in `s` (see `Rejects::contains`),
+    /// in increasing order.
+    pub fn matches(&self, s: &str) -> Vec<usize> {
+        self.patterns
+            .iter()
+            .enumerate()
+            .filter(|(_, rejects)| rejects.contains(s))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// returns true if any of the patterns matches anywhere in `s`.
+    pub fn is_match(&self, s: &str) -> bool {
+        self.patterns.iter().any(|rejects| rejects.contains(s))
+    }
+
+    /// returns the index of the pattern with the longest match at the start of `s`, along with
+    /// the length (in bytes) of that match. When several patterns match the same longest prefix
+    /// the one with the lowest index is reported.
+    ///
+    /// Unlike `matches` all of the patterns are run together in a single pass over `s`.
+    pub fn longest_match(&self, s: &str) -> Option<(usize, usize)> {
+        let mut states = self.union.start_states();
+        let mut longest = None;
+        let mut prev = None;
+        for (i, c) in s.char_indices() {
+            // the matches of the first i bytes, a '$' in them is only settled by seeing `c`
+            let next = Next::of(&s[i..]);
+            if let Some(id) = self.accepted(&self.union.states_before(&states, prev, next)) {
+                longest = Some((id, i));
+            }
+            states = self.union.step(&states, prev, next);
+            if states.is_empty() {
+                return longest;
+            }
+            prev = Some(c);
+        }
+        if let Some(id) = self.accepted(&self.union.states_before(&states, prev, Next::End)) {
+            longest = Some((id, s.len()));
+        }
+        longest
+    }
+
+    // The lowest index of the patterns whose Match states are in `states`.
+    fn accepted(&self, states: &HashSet<usize>) -> Option<usize> {
+        states
+            .iter()
+            .filter_map(|n| self.match_ids.get(n))
+            .min()
+            .copied()
+    }
+
+    /// returns the number of states in the machine `longest_match` runs.
+    pub fn state_count(&self) -> usize {
+        self.union.state_count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+// Copies the states of each of `patterns` into one machine, with a Split in front of each pattern
+// leading into it or on to the next pattern's Split. The returned map gives the pattern each
+// Match state belongs to.
+fn union(patterns: &[Rejects]) -> (Rejects, HashMap<usize, usize>) {
+    let mut states = Vec::new();
+    let mut match_ids = HashMap::new();
+    let mut starts = Vec::new();
+    for (id, rejects) in patterns.iter().enumerate() {
+        let base = states.len();
+        starts.push(rejects.start + base);
+        for state in rejects.statelist.iter() {
+            if let State::Match = state {
+                match_ids.insert(states.len(), id);
+            }
+            states.push(state.shifted(base));
+        }
+    }
+    if starts.is_empty() {
+        // a machine which never matches
+        return (Rejects::from(0, vec![State::make_nil()]), match_ids);
+    }
+
+    let mut start = starts.pop().unwrap();
+    for &out1 in starts.iter().rev() {
+        states.push(State::make_split(out1, Some(start)));
+        start = states.len() - 1;
+    }
+    (Rejects::from(start, states), match_ids)
+}
+
+// A beginning shared by some of the patterns, see `RejectsSet::compile_trie`. Each child is
+// reached by matching its piece of the patterns which go through it.
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: Vec<(Ast, TrieNode)>,
+    // the patterns which end here
+    ids: Vec<usize>,
+}
+
+impl TrieNode {
+    fn insert(&mut self, atoms: Vec<Ast>, id: usize) {
+        let mut node = self;
+        for atom in atoms {
+            let i = match node.children.iter().position(|(a, _)| *a == atom) {
+                Some(i) => i,
+                None => {
+                    node.children.push((atom, TrieNode::default()));
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[i].1;
+        }
+        node.ids.push(id);
+    }
+
+    // Compiles this node and everything below it, returning the state to start from. Each
+    // pattern ending here gets its own Match state, recorded in `match_ids`.
+    fn compile(&self, statelist: &mut StateList, match_ids: &mut HashMap<usize, usize>) -> usize {
+        let mut starts = Vec::new();
+        for &id in &self.ids {
+            let n = statelist.add_state(State::make_match());
+            match_ids.insert(n, id);
+            starts.push(n);
+        }
+        for (atom, child) in &self.children {
+            let frag = atom.compile(statelist);
+            let rest = child.compile(statelist, match_ids);
+            for &dangler in &frag.endstates {
+                statelist.link(dangler, rest);
+            }
+            starts.push(frag.start);
+        }
+
+        // every node has a pattern ending at it or below it
+        let mut start = starts.pop().unwrap();
+        for &out1 in starts.iter().rev() {
+            start = statelist.add_state(State::make_split(out1, Some(start)));
+        }
+        start
+    }
+}
+
+// The pieces `ast` is a concatenation of, in order.
+fn atoms_of(ast: Ast, atoms: &mut Vec<Ast>) {
+    match ast {
+        Ast::Concat(l, r) => {
+            atoms_of(*l, atoms);
+            atoms_of(*r, atoms);
+        }
+        _ => atoms.push(ast),
+    }
+}
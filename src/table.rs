@@ -0,0 +1,169 @@
+use crate::nfa::{Anchor, Next};
+
+/// A state of a `StaticRejects`. The same as `nfa::State` but with its character sets as sorted
+/// slices, so a whole machine can be written out as a `const`.
+#[derive(Debug)]
+pub enum StaticState {
+    Transition {
+        inclusive: &'static [char],
+        exclusive: &'static [char],
+        out: Option<usize>,
+    },
+    Split {
+        out1: usize,
+        out2: Option<usize>,
+    },
+    Anchor {
+        anchor: Anchor,
+        out: Option<usize>,
+    },
+    Match,
+    Nil,
+}
+
+impl StaticState {
+    fn transition(&self, c: char) -> Option<usize> {
+        match self {
+            StaticState::Transition {
+                inclusive,
+                exclusive,
+                out,
+            } => {
+                if (!inclusive.is_empty() && inclusive.binary_search(&c).is_ok())
+                    || (!exclusive.is_empty() && exclusive.binary_search(&c).is_err())
+                    || (inclusive.is_empty() && exclusive.is_empty())
+                {
+                    *out
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A machine which borrows its states from a table instead of owning them, so one generated at
+/// compile time (see `Rejects::to_static_tokens`) can be a `const` or `static` and nothing is
+/// built when the program starts.
+///
+/// Only matching from the beginning of the input is supported.
+#[derive(Debug)]
+pub struct StaticRejects {
+    start: usize,
+    states: &'static [StaticState],
+}
+
+impl StaticRejects {
+    /// The sets of every `StaticState::Transition` in `states` must be sorted.
+    pub const fn new(start: usize, states: &'static [StaticState]) -> StaticRejects {
+        StaticRejects { start, states }
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    /// returns the length (in characters) of every prefix of `s` which the pattern matches, in
+    /// increasing order.
+    pub fn run_to_completion(&self, s: &str) -> Vec<usize> {
+        let mut accepting = Vec::new();
+        self.run(s, |len| {
+            accepting.push(len);
+            true
+        });
+        accepting
+    }
+
+    /// returns true if the pattern matches some prefix of `s`, possibly the empty one.
+    pub fn starts_with_match(&self, s: &str) -> bool {
+        let mut found = false;
+        self.run(s, |_| {
+            found = true;
+            false
+        });
+        found
+    }
+
+    /// returns true if the pattern matches all of `s`.
+    pub fn is_full_match(&self, s: &str) -> bool {
+        let len = s.chars().count();
+        let mut found = false;
+        self.run(s, |n| {
+            found = n == len;
+            true
+        });
+        found
+    }
+
+    // Runs the machine over `s`, calling `on_match` with the length of each matched prefix until
+    // it returns false or no state is left.
+    fn run<F: FnMut(usize) -> bool>(&self, s: &str, mut on_match: F) {
+        // two sets of states, each a list for iterating over and a flag per state for membership
+        let mut cur = Vec::with_capacity(self.states.len());
+        let mut next = Vec::with_capacity(self.states.len());
+        let mut on = vec![false; self.states.len()];
+        // unlike Rejects this has all of the input, so a '$' can be settled when it is reached
+        let mut chars = s.char_indices();
+        self.add(&mut cur, &mut on, self.start, (None, Next::of(s)));
+
+        let mut len = 0;
+        loop {
+            if cur
+                .iter()
+                .any(|&n| matches!(self.states[n], StaticState::Match))
+                && !on_match(len)
+            {
+                return;
+            }
+            let (i, c) = match chars.next() {
+                Some(next) => next,
+                None => return,
+            };
+            for &n in cur.iter() {
+                on[n] = false;
+            }
+            let at = (Some(c), Next::of(&s[i + c.len_utf8()..]));
+            for &n in cur.iter() {
+                if let Some(out) = self.states[n].transition(c) {
+                    self.add(&mut next, &mut on, out, at);
+                }
+            }
+            if next.is_empty() {
+                return;
+            }
+            cur.clear();
+            std::mem::swap(&mut cur, &mut next);
+            len += 1;
+        }
+    }
+
+    // Adds `state` and its epsilon closure to `states`, going through the anchors which hold where
+    // `at` says the states are (the character before them and what comes after them).
+    fn add(
+        &self,
+        states: &mut Vec<usize>,
+        on: &mut [bool],
+        state: usize,
+        (prev, next): (Option<char>, Next),
+    ) {
+        let mut stack = vec![state];
+        while let Some(n) = stack.pop() {
+            if on[n] {
+                continue;
+            }
+            on[n] = true;
+            states.push(n);
+            match self.states[n] {
+                StaticState::Split { out1, out2 } => {
+                    stack.extend(out2);
+                    stack.push(out1);
+                }
+                StaticState::Anchor { anchor, out } if anchor.holds(prev, next) => {
+                    stack.extend(out)
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use rejects::make_rejects;
+use rejects::rejects::Rejects;
+
+// The macro compiles its pattern with the rejects pinned in Cargo.lock, not with this crate, so
+// its machine can be laid out differently. It still has to agree on every input for the syntax
+// both parsers understand.
+fn assert_same(compiled: Rejects, pat: &str, inputs: &[&str]) {
+    let runtime = Rejects::new(pat).unwrap();
+    for s in inputs {
+        assert_eq!(compiled.find_end(s), runtime.find_end(s), "{} {}", pat, s);
+        assert_eq!(
+            compiled.run_to_completion(s),
+            runtime.run_to_completion(s),
+            "{} {}",
+            pat,
+            s
+        );
+        assert_eq!(compiled.contains(s), runtime.contains(s), "{} {}", pat, s);
+    }
+}
+
+#[test]
+fn test_macro_matches_runtime() {
+    let inputs = [
+        "", "a", "aa", "ab", "abab", "abc", "b", "cd", "x", "12", "a1b2", "a b", "é", "aXc",
+    ];
+    assert_same(make_rejects!(r"a*"), r"a*", &inputs);
+    assert_same(make_rejects!(r"a+b?"), r"a+b?", &inputs);
+    assert_same(make_rejects!(r"(ab)*c?"), r"(ab)*c?", &inputs);
+    assert_same(make_rejects!(r"(ab|cd)+"), r"(ab|cd)+", &inputs);
+    assert_same(make_rejects!(r"a|b|cd"), r"a|b|cd", &inputs);
+    assert_same(make_rejects!(r"\d+\w?"), r"\d+\w?", &inputs);
+    assert_same(make_rejects!(r"a\s+b"), r"a\s+b", &inputs);
+    assert_same(make_rejects!(r"[^ab]+"), r"[^ab]+", &inputs);
+    assert_same(make_rejects!(r"a.c"), r"a.c", &inputs);
+    assert_same(make_rejects!(r"(a|b)*(ab)?"), r"(a|b)*(ab)?", &inputs);
+    assert_same(make_rejects!(r"\\\*\(\)"), r"\\\*\(\)", &inputs);
+}